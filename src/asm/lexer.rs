@@ -0,0 +1,104 @@
+// Module splitting a line of assembly into tokens
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    /// A mnemonic, directive, label, register or number.
+    Word(String),
+    /// A double quoted string literal, with its escapes resolved.
+    Str(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Str(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// Splits `line` on whitespace and commas, dropping everything after a `;` comment.
+pub(crate) fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() || c == ',' => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err("unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => s.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some('e') => '\x1B',
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            Some(other) => return Err(format!("unknown escape '\\{}'", other)),
+                            None => return Err("unterminated string".to_string()),
+                        }),
+                        Some(c) if c.is_ascii() => s.push(c),
+                        Some(c) => return Err(format!("non-ASCII character '{}' in string", c)),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(s: &str) -> Token {
+        Token::Word(s.to_string())
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("LOOP ADD R1,R1, #-1 ; count down").unwrap(),
+            vec![
+                word("LOOP"),
+                word("ADD"),
+                word("R1"),
+                word("R1"),
+                word("#-1")
+            ]
+        );
+        assert_eq!(
+            tokenize(r#"MSG .STRINGZ "a; b\n""#).unwrap(),
+            vec![
+                word("MSG"),
+                word(".STRINGZ"),
+                Token::Str("a; b\n".to_string())
+            ]
+        );
+        assert!(tokenize("; only a comment").unwrap().is_empty());
+        assert!(tokenize(r#".STRINGZ "open"#).is_err());
+    }
+}
@@ -0,0 +1,622 @@
+// Module for the LC-3 assembler, turning assembly source into object images
+
+mod lexer;
+mod source_map;
+
+use crate::vm::{ObjectImage, OpCode, SymbolTable, TrapCode};
+use lexer::{tokenize, Token};
+pub use source_map::SourceMap;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An assembly error, with the 1-based source line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        AsmError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// The result of assembling a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub image: ObjectImage,
+    pub symbols: SymbolTable,
+    /// The source line of every instruction. Its `source` path is left for the caller to fill in.
+    pub source_map: SourceMap,
+}
+
+/* a source line after the first pass: what it emits and where */
+struct Statement {
+    line: usize,
+    address: u16,
+    mnemonic: String,
+    operands: Vec<Token>,
+}
+
+/// Assembles LC-3 assembly source into an object image.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let (origin, statements, symbols) = first_pass(source)?;
+
+    let table: HashMap<&str, u16> = symbols
+        .iter()
+        .map(|(name, address)| (name.as_str(), *address))
+        .collect();
+    let mut words = Vec::new();
+    for statement in &statements {
+        encode(statement, &table, &mut words)?;
+    }
+
+    let mut symbol_table = SymbolTable::new();
+    for (name, address) in &symbols {
+        symbol_table.insert(name, *address);
+    }
+    let mut source_map = SourceMap::new();
+    for statement in statements.iter().filter(|s| !s.mnemonic.starts_with('.')) {
+        source_map.insert(statement.address, statement.line);
+    }
+    Ok(Assembly {
+        image: ObjectImage::new(origin, words),
+        symbols: symbol_table,
+        source_map,
+    })
+}
+
+/* assigns an address to every statement and collects the labels */
+#[allow(clippy::type_complexity)]
+fn first_pass(source: &str) -> Result<(u16, Vec<Statement>, Vec<(String, u16)>), AsmError> {
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut statements = Vec::new();
+    let mut symbols: Vec<(String, u16)> = Vec::new();
+    let mut ended = false;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut tokens = tokenize(text).map_err(|message| AsmError::new(line, message))?;
+        if tokens.is_empty() {
+            continue;
+        }
+        if ended {
+            return Err(AsmError::new(line, "code after .END"));
+        }
+
+        /* a leading word which is not an instruction or directive is a label */
+        if let Token::Word(word) = &tokens[0] {
+            if word.starts_with('.') && !is_mnemonic(word) {
+                return Err(AsmError::new(
+                    line,
+                    format!("unknown directive '{}'", word.to_uppercase()),
+                ));
+            }
+            if !is_mnemonic(word) {
+                let label = word.strip_suffix(':').unwrap_or(word).to_string();
+                if !is_valid_label(&label) {
+                    return Err(AsmError::new(line, format!("invalid label '{}'", label)));
+                }
+                if origin.is_none() {
+                    return Err(AsmError::new(line, "label before .ORIG"));
+                }
+                if symbols.iter().any(|(name, _)| *name == label) {
+                    return Err(AsmError::new(line, format!("duplicate label '{}'", label)));
+                }
+                symbols.push((label, address as u16));
+                tokens.remove(0);
+            }
+        }
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let mnemonic = match tokens.remove(0) {
+            Token::Word(word) => word.to_uppercase(),
+            Token::Str(_) => return Err(AsmError::new(line, "unexpected string")),
+        };
+
+        match mnemonic.as_str() {
+            ".ORIG" => {
+                if origin.is_some() {
+                    return Err(AsmError::new(line, "duplicate .ORIG"));
+                }
+                let value = expect_operands(line, &tokens, 1)
+                    .and_then(|_| number_operand(line, &tokens[0], 0, 0xFFFF))?;
+                origin = Some(value as u16);
+                address = value as u32;
+                continue;
+            }
+            ".END" => {
+                ended = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if origin.is_none() {
+            return Err(AsmError::new(line, "code before .ORIG"));
+        }
+        let size = statement_size(line, &mnemonic, &tokens)?;
+        if address + size > 0x10000 {
+            return Err(AsmError::new(line, "program does not fit in memory"));
+        }
+        statements.push(Statement {
+            line,
+            address: address as u16,
+            mnemonic,
+            operands: tokens,
+        });
+        address += size;
+    }
+
+    match origin {
+        Some(origin) => Ok((origin, statements, symbols)),
+        None => Err(AsmError::new(
+            source.lines().count().max(1),
+            "missing .ORIG",
+        )),
+    }
+}
+
+/* number of words a statement occupies */
+fn statement_size(line: usize, mnemonic: &str, operands: &[Token]) -> Result<u32, AsmError> {
+    match mnemonic {
+        ".BLKW" => {
+            expect_operands(line, operands, 1)?;
+            Ok(number_operand(line, &operands[0], 0, 0xFFFF)? as u32)
+        }
+        ".STRINGZ" => {
+            expect_operands(line, operands, 1)?;
+            match &operands[0] {
+                Token::Str(s) => Ok(s.len() as u32 + 1),
+                Token::Word(_) => Err(AsmError::new(line, ".STRINGZ expects a string")),
+            }
+        }
+        _ => Ok(1),
+    }
+}
+
+fn encode(
+    statement: &Statement,
+    symbols: &HashMap<&str, u16>,
+    words: &mut Vec<u16>,
+) -> Result<(), AsmError> {
+    let line = statement.line;
+    let ops = &statement.operands;
+    let op = |opcode: OpCode| u16::from(opcode) << 12;
+    let reg = |index: usize| register_operand(line, &ops[index]);
+    let offset =
+        |index: usize, bits: u32| pc_offset(line, &ops[index], statement.address, bits, symbols);
+
+    let word = match statement.mnemonic.as_str() {
+        "ADD" | "AND" => {
+            expect_operands(line, ops, 3)?;
+            let opcode = if statement.mnemonic == "ADD" {
+                OpCode::Add
+            } else {
+                OpCode::And
+            };
+            let base = op(opcode) | reg(0)? << 9 | reg(1)? << 6;
+            if is_register(&ops[2]) {
+                base | reg(2)?
+            } else {
+                base | 1 << 5 | immediate(line, &ops[2], 5)?
+            }
+        }
+        "NOT" => {
+            expect_operands(line, ops, 2)?;
+            op(OpCode::Not) | reg(0)? << 9 | reg(1)? << 6 | 0x3F
+        }
+        "JMP" => {
+            expect_operands(line, ops, 1)?;
+            op(OpCode::Jmp) | reg(0)? << 6
+        }
+        "RET" => {
+            expect_operands(line, ops, 0)?;
+            op(OpCode::Jmp) | 7 << 6
+        }
+        "JSR" => {
+            expect_operands(line, ops, 1)?;
+            op(OpCode::Jsr) | 1 << 11 | offset(0, 11)?
+        }
+        "JSRR" => {
+            expect_operands(line, ops, 1)?;
+            op(OpCode::Jsr) | reg(0)? << 6
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect_operands(line, ops, 2)?;
+            let opcode = match statement.mnemonic.as_str() {
+                "LD" => OpCode::Ld,
+                "LDI" => OpCode::Ldi,
+                "LEA" => OpCode::Lea,
+                "ST" => OpCode::St,
+                _ => OpCode::Sti,
+            };
+            op(opcode) | reg(0)? << 9 | offset(1, 9)?
+        }
+        "LDR" | "STR" => {
+            expect_operands(line, ops, 3)?;
+            let opcode = if statement.mnemonic == "LDR" {
+                OpCode::Ldr
+            } else {
+                OpCode::Str
+            };
+            op(opcode) | reg(0)? << 9 | reg(1)? << 6 | immediate(line, &ops[2], 6)?
+        }
+        "TRAP" => {
+            expect_operands(line, ops, 1)?;
+            op(OpCode::Trap) | number_operand(line, &ops[0], 0, 0xFF)? as u16
+        }
+        "RTI" => {
+            expect_operands(line, ops, 0)?;
+            op(OpCode::Rti)
+        }
+        ".FILL" => {
+            expect_operands(line, ops, 1)?;
+            match &ops[0] {
+                Token::Word(word) if symbols.contains_key(word.as_str()) => symbols[word.as_str()],
+                Token::Word(word) if is_valid_label(word) => {
+                    return Err(AsmError::new(line, format!("undefined label '{}'", word)));
+                }
+                token => number_operand(line, token, -0x8000, 0xFFFF)? as u16,
+            }
+        }
+        ".BLKW" => {
+            let count = number_operand(line, &ops[0], 0, 0xFFFF)?;
+            words.resize(words.len() + count as usize, 0);
+            return Ok(());
+        }
+        ".STRINGZ" => {
+            if let Token::Str(s) = &ops[0] {
+                words.extend(s.bytes().map(u16::from));
+            }
+            words.push(0);
+            return Ok(());
+        }
+        mnemonic => {
+            if let Some(trap) = trap_alias(mnemonic) {
+                expect_operands(line, ops, 0)?;
+                op(OpCode::Trap) | u16::from(trap)
+            } else if let Some(flags) = branch_flags(mnemonic) {
+                expect_operands(line, ops, 1)?;
+                op(OpCode::Br) | flags << 9 | offset(0, 9)?
+            } else {
+                return Err(AsmError::new(
+                    line,
+                    format!("unknown opcode '{}'", mnemonic),
+                ));
+            }
+        }
+    };
+    words.push(word);
+    Ok(())
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    let word = word.to_uppercase();
+    matches!(
+        word.as_str(),
+        "ADD"
+            | "AND"
+            | "NOT"
+            | "JMP"
+            | "RET"
+            | "JSR"
+            | "JSRR"
+            | "LD"
+            | "LDI"
+            | "LDR"
+            | "LEA"
+            | "ST"
+            | "STI"
+            | "STR"
+            | "TRAP"
+            | "RTI"
+            | ".ORIG"
+            | ".FILL"
+            | ".BLKW"
+            | ".STRINGZ"
+            | ".END"
+    ) || trap_alias(&word).is_some()
+        || branch_flags(&word).is_some()
+}
+
+fn trap_alias(mnemonic: &str) -> Option<TrapCode> {
+    Some(match mnemonic {
+        "GETC" => TrapCode::Getc,
+        "OUT" => TrapCode::Out,
+        "PUTS" => TrapCode::Puts,
+        "IN" => TrapCode::In,
+        "PUTSP" => TrapCode::Putsp,
+        "HALT" => TrapCode::Halt,
+        _ => return None,
+    })
+}
+
+/* the n, z and p bits of a BR mnemonic; a bare BR branches unconditionally */
+fn branch_flags(mnemonic: &str) -> Option<u16> {
+    let suffix = mnemonic.strip_prefix("BR")?;
+    if suffix.is_empty() {
+        return Some(0b111);
+    }
+    let mut flags = 0;
+    for c in suffix.chars() {
+        let bit = match c {
+            'N' => 0b100,
+            'Z' => 0b010,
+            'P' => 0b001,
+            _ => return None,
+        };
+        if flags & bit != 0 {
+            return None;
+        }
+        flags |= bit;
+    }
+    Some(flags)
+}
+
+fn is_valid_label(label: &str) -> bool {
+    let mut chars = label.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_register(label).is_none()
+        && parse_number(label).is_none()
+}
+
+fn expect_operands(line: usize, operands: &[Token], count: usize) -> Result<(), AsmError> {
+    if operands.len() != count {
+        return Err(AsmError::new(
+            line,
+            format!("expected {} operand(s), found {}", count, operands.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_register(word: &str) -> Option<u16> {
+    let digit = word.strip_prefix(['R', 'r'])?;
+    match digit.parse::<u16>() {
+        Ok(n) if n < 8 && digit.len() == 1 => Some(n),
+        _ => None,
+    }
+}
+
+fn is_register(token: &Token) -> bool {
+    matches!(token, Token::Word(word) if parse_register(word).is_some())
+}
+
+fn register_operand(line: usize, token: &Token) -> Result<u16, AsmError> {
+    match token {
+        Token::Word(word) => parse_register(word),
+        Token::Str(_) => None,
+    }
+    .ok_or_else(|| AsmError::new(line, format!("expected a register, found {}", token)))
+}
+
+/// Parses a numeric literal: `#10`, `#-3`, `x3000`, `0x3000` or a plain decimal.
+pub fn parse_number(word: &str) -> Option<i32> {
+    let (negative, digits, radix) = if let Some(rest) = word.strip_prefix('#') {
+        match rest.strip_prefix('-') {
+            Some(rest) => (true, rest, 10),
+            None => (false, rest, 10),
+        }
+    } else if let Some(rest) = word
+        .strip_prefix("0x")
+        .or_else(|| word.strip_prefix("0X"))
+        .or_else(|| word.strip_prefix(['x', 'X']))
+    {
+        match rest.strip_prefix('-') {
+            Some(rest) => (true, rest, 16),
+            None => (false, rest, 16),
+        }
+    } else {
+        match word.strip_prefix('-') {
+            Some(rest) => (true, rest, 10),
+            None => (false, word, 10),
+        }
+    };
+    if digits.is_empty() || digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+fn number_operand(line: usize, token: &Token, min: i32, max: i32) -> Result<i32, AsmError> {
+    let value = match token {
+        Token::Word(word) => parse_number(word),
+        Token::Str(_) => None,
+    }
+    .ok_or_else(|| AsmError::new(line, format!("expected a number, found {}", token)))?;
+    if value < min || value > max {
+        return Err(AsmError::new(
+            line,
+            format!("{} is out of range [{}, {}]", value, min, max),
+        ));
+    }
+    Ok(value)
+}
+
+/* a sign-extended immediate field of `bits` bits */
+fn immediate(line: usize, token: &Token, bits: u32) -> Result<u16, AsmError> {
+    let max = (1 << (bits - 1)) - 1;
+    let value = number_operand(line, token, -max - 1, max)?;
+    Ok(value as u16 & ((1 << bits) - 1))
+}
+
+/* a PC-relative offset field, given either as a label or as a literal offset */
+fn pc_offset(
+    line: usize,
+    token: &Token,
+    address: u16,
+    bits: u32,
+    symbols: &HashMap<&str, u16>,
+) -> Result<u16, AsmError> {
+    let target = match token {
+        Token::Word(word) if parse_number(word).is_none() => *symbols
+            .get(word.as_str())
+            .ok_or_else(|| AsmError::new(line, format!("undefined label '{}'", word)))?,
+        _ => return immediate(line, token, bits),
+    };
+    let offset = target as i32 - (address as i32 + 1);
+    let max = (1 << (bits - 1)) - 1;
+    if offset < -max - 1 || offset > max {
+        return Err(AsmError::new(
+            line,
+            format!("{} is too far away ({} words)", token, offset),
+        ));
+    }
+    Ok(offset as u16 & ((1 << bits) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_all_opcodes() {
+        let source = "
+            .ORIG x3000
+    START   ADD R1, R2, R3      ; register mode
+            ADD R1, R2, #-1
+            AND R0, R0, #0
+            NOT R4, R5
+            BRnzp START
+            BR START
+            BRz DATA
+            JMP R2
+            RET
+            JSR START
+            JSRR R3
+            LD R0, DATA
+            LDI R1, DATA
+            LDR R2, R6, #-2
+            LEA R3, MSG
+            ST R0, DATA
+            STI R1, DATA
+            STR R2, R6, #3
+            TRAP x25
+            RTI
+    DATA    .FILL xBEEF
+    MSG     .STRINGZ \"Hi\\n\"
+            .BLKW 2
+            .END
+        ";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(assembly.image.origin, 0x3000);
+        assert_eq!(
+            assembly.image.words,
+            vec![
+                0x1283, 0x12BF, 0x5020, 0x997F, 0x0FFB, 0x0FFA, 0x040D, 0xC080, 0xC1C0, 0x4FF6,
+                0x40C0, 0x2008, 0xA207, 0x65BE, 0xE606, 0x3004, 0xB203, 0x7583, 0xF025, 0x8000,
+                0xBEEF, 0x0048, 0x0069, 0x000A, 0x0000, 0x0000, 0x0000,
+            ]
+        );
+        assert_eq!(
+            assembly.symbols.iter().collect::<Vec<_>>(),
+            vec![(0x3000, "START"), (0x3014, "DATA"), (0x3015, "MSG")]
+        );
+    }
+
+    #[test]
+    fn test_assemble_trap_aliases() {
+        let source = ".orig x3000\ngetc\nOUT\nPUTS\nIN\nPUTSP\nHALT\n.end";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(
+            assembly.image.words,
+            vec![0xF020, 0xF021, 0xF022, 0xF023, 0xF024, 0xF025]
+        );
+    }
+
+    #[test]
+    fn test_fill_label_and_literal_offsets() {
+        let source = ".ORIG x4000\nPTR .FILL PTR\nBRp #-2\nLDR R0, R0, x1F\n.END";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(assembly.image.words, vec![0x4000, 0x03FE, 0x601F]);
+    }
+
+    #[test]
+    fn test_source_map_lists_instructions() {
+        let source = ".ORIG x3000\n\nLOOP ADD R0, R0, #1\nMSG .STRINGZ \"hi\"\n  BRnzp LOOP\n.END";
+        let assembly = assemble(source).unwrap();
+        let lines: Vec<(u16, usize)> = assembly.source_map.iter().collect();
+
+        assert_eq!(lines, vec![(0x3000, 3), (0x3004, 5)]);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = |source: &str| assemble(source).unwrap_err();
+
+        assert_eq!(
+            error("ADD R0, R0, #1"),
+            AsmError::new(1, "code before .ORIG")
+        );
+        assert_eq!(
+            error(".ORIG x3000\n\nADD R0, R0, #16\n.END"),
+            AsmError::new(3, "16 is out of range [-16, 15]")
+        );
+        assert_eq!(
+            error(".ORIG x3000\nBR NOWHERE\n.END"),
+            AsmError::new(2, "undefined label 'NOWHERE'")
+        );
+        assert_eq!(
+            error(".ORIG x3000\nA ADD R0, R0\n.END"),
+            AsmError::new(2, "expected 3 operand(s), found 2")
+        );
+        assert_eq!(
+            error(".ORIG x3000\nA .FILL 1\nA .FILL 2\n.END"),
+            AsmError::new(3, "duplicate label 'A'")
+        );
+        assert_eq!(
+            error(".ORIG x3000\nLD R8, #1\n.END"),
+            AsmError::new(2, "expected a register, found 'R8'")
+        );
+        assert_eq!(
+            error(".ORIG x3000\n1ABC .FILL 1\n.END"),
+            AsmError::new(2, "invalid label '1ABC'")
+        );
+        /* it would read back as the number xAB */
+        assert_eq!(
+            error(".ORIG x3000\nxAB .FILL 1\n.END"),
+            AsmError::new(2, "invalid label 'xAB'")
+        );
+        assert_eq!(
+            error(".ORIG x3000\n.FILL UNDEFINED\n.END"),
+            AsmError::new(2, "undefined label 'UNDEFINED'")
+        );
+        assert_eq!(
+            error(".ORIG x3000\n.BOGUS 1\n.END"),
+            AsmError::new(2, "unknown directive '.BOGUS'")
+        );
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("#10"), Some(10));
+        assert_eq!(parse_number("#-10"), Some(-10));
+        assert_eq!(parse_number("x3000"), Some(0x3000));
+        assert_eq!(parse_number("0xFF"), Some(0xFF));
+        assert_eq!(parse_number("x-1"), Some(-1));
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("LOOP"), None);
+        assert_eq!(parse_number("#"), None);
+    }
+}
@@ -0,0 +1,124 @@
+// Module for source maps, mapping instruction addresses back to assembly source lines
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// The source line each instruction of an image was assembled from, as written to and
+/// read from `.map` files next to the image:
+///
+/// ```text
+/// source hello.asm
+/// x3000 2
+/// x3001 3
+/// ```
+///
+/// Only instructions are listed; data (`.FILL`, `.BLKW`, `.STRINGZ`) is not.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// The path of the source file, as given to the assembler.
+    pub source: String,
+    lines: BTreeMap<u16, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, address: u16, line: usize) {
+        self.lines.insert(address, line);
+    }
+
+    /// The 1-based source line of the instruction at `address`.
+    pub fn line_at(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// Instructions in address order, with their source line.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.lines.iter().map(|(&address, &line)| (address, line))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = SourceMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let entry = match fields.as_slice() {
+                [] => continue,
+                ["source", ..] => {
+                    map.source = line.trim()["source".len()..].trim().to_string();
+                    continue;
+                }
+                [address, line] => address
+                    .strip_prefix(['x', 'X'])
+                    .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+                    .zip(line.parse().ok()),
+                _ => None,
+            };
+            match entry {
+                Some((address, line)) => map.insert(address, line),
+                None => {
+                    return Err(format!(
+                        "line {}: expected an address and a line number, found '{}'",
+                        index + 1,
+                        line.trim()
+                    ))
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_map_string(&self) -> String {
+        let mut text = format!("source {}\n", self.source);
+        for (address, line) in self.iter() {
+            text.push_str(&format!("x{:04X} {}\n", address, line));
+        }
+        text
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_map_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_round_trip() {
+        let mut map = SourceMap::new();
+        map.source = "my programs/hello.asm".to_string();
+        map.insert(0x3001, 3);
+        map.insert(0x3000, 2);
+
+        let text = map.to_map_string();
+        assert_eq!(text, "source my programs/hello.asm\nx3000 2\nx3001 3\n");
+        assert_eq!(SourceMap::parse(&text).unwrap(), map);
+        assert_eq!(map.line_at(0x3001), Some(3));
+        assert_eq!(map.line_at(0x3002), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            SourceMap::parse("x3000 2\n3001 3\n").unwrap_err(),
+            "line 2: expected an address and a line number, found '3001 3'"
+        );
+    }
+}
@@ -0,0 +1,301 @@
+// Module for code coverage: which instructions of an image were executed
+
+use crate::asm::SourceMap;
+use crate::disasm::disassemble_image;
+use crate::vm::{ObjectImage, OpCode, Register, StepResult, VM};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+
+/// How many times a conditional branch was taken and not taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Coverage totals over the instructions of an image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoverageSummary {
+    pub instructions: usize,
+    pub executed: usize,
+    /// Two outcomes, taken and not taken, per conditional branch. A `BRnzp`, always
+    /// taken, and a `BR` with no condition bits, never taken, are not counted.
+    pub branch_outcomes: usize,
+    pub covered_outcomes: usize,
+}
+
+impl fmt::Display for CoverageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |part: usize, total: usize| 100.0 * part as f64 / total.max(1) as f64;
+        write!(
+            f,
+            "{}/{} instructions executed ({:.2}%), {}/{} branch outcomes ({:.2}%)",
+            self.executed,
+            self.instructions,
+            percent(self.executed, self.instructions),
+            self.covered_outcomes,
+            self.branch_outcomes,
+            percent(self.covered_outcomes, self.branch_outcomes)
+        )
+    }
+}
+
+/// Records which addresses were executed and which way conditional branches went.
+///
+/// Feed it the instructions as they execute, e.g. from `VM::run_until`, then report on
+/// an image. With the image's source map, only its instructions count and the report
+/// can be keyed by source line; without one, every word of the image counts.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    counts: Vec<u64>,
+    branches: BTreeMap<u16, BranchCoverage>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            counts: vec![0; 1 << 16],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Records an instruction `vm` just executed.
+    pub fn record(&mut self, vm: &VM, result: &StepResult) {
+        self.counts[result.pc_before as usize] += 1;
+        if let Some(nzp) = conditional_branch(result.instruction) {
+            /* BR leaves the condition codes alone, so they are still the ones it tested */
+            let branch = self.branches.entry(result.pc_before).or_default();
+            if nzp & vm.reg(Register::Cond) != 0 {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    /// How many times the instruction at `address` was executed.
+    pub fn count_at(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// The outcomes of the conditional branch at `address`, if it was executed.
+    pub fn branch_at(&self, address: u16) -> Option<BranchCoverage> {
+        self.branches.get(&address).copied()
+    }
+
+    pub fn summary(&self, image: &ObjectImage, map: Option<&SourceMap>) -> CoverageSummary {
+        let mut summary = CoverageSummary::default();
+        for (address, word) in instructions(image, map) {
+            summary.instructions += 1;
+            if self.count_at(address) > 0 {
+                summary.executed += 1;
+            }
+            if conditional_branch(word).is_some() {
+                let branch = self.branch_at(address).unwrap_or_default();
+                summary.branch_outcomes += 2;
+                summary.covered_outcomes +=
+                    usize::from(branch.taken > 0) + usize::from(branch.not_taken > 0);
+            }
+        }
+        summary
+    }
+
+    /// The summary followed by the disassembly of `image`, each instruction annotated with
+    /// its execution count (`#####` if never executed) and each conditional branch with its
+    /// outcomes. Data words, known from the source map, are marked `-`.
+    pub fn report(&self, vm: &VM, image: &ObjectImage, map: Option<&SourceMap>) -> String {
+        let mut report = format!("Coverage: {}\n\n", self.summary(image, map));
+        for line in disassemble_image(image, vm.symbols()) {
+            let is_instruction = map.map_or(true, |map| map.line_at(line.address).is_some());
+            let count = match self.count_at(line.address) {
+                _ if !is_instruction => "-".to_string(),
+                0 => "#####".to_string(),
+                count => count.to_string(),
+            };
+            let _ = write!(report, "{:>10}  {}", count, line);
+            if is_instruction && conditional_branch(line.word).is_some() {
+                let branch = self.branch_at(line.address).unwrap_or_default();
+                let _ = write!(
+                    report,
+                    "    [taken {}, not taken {}]",
+                    branch.taken, branch.not_taken
+                );
+            }
+            report.push('\n');
+        }
+        report
+    }
+
+    /// An lcov tracefile record for the source of `image`, keyed by the lines of `map`.
+    pub fn lcov(&self, image: &ObjectImage, map: &SourceMap) -> String {
+        let mut record = format!("TN:\nSF:{}\n", map.source);
+        let mut lines = Vec::new();
+        let (mut branches, mut branches_hit) = (0, 0);
+        for (address, word) in instructions(image, Some(map)) {
+            let line = map.line_at(address).unwrap_or_default();
+            let count = self.count_at(address);
+            lines.push((line, count));
+            if conditional_branch(word).is_none() {
+                continue;
+            }
+            let branch = self.branch_at(address).unwrap_or_default();
+            for (index, outcome) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                /* lcov writes "-" for the branches of a line never executed */
+                let taken = match count {
+                    0 => "-".to_string(),
+                    _ => outcome.to_string(),
+                };
+                let _ = writeln!(record, "BRDA:{},0,{},{}", line, index, taken);
+                branches += 1;
+                branches_hit += usize::from(outcome > 0);
+            }
+        }
+        let _ = writeln!(record, "BRF:{}\nBRH:{}", branches, branches_hit);
+        for (line, count) in &lines {
+            let _ = writeln!(record, "DA:{},{}", line, count);
+        }
+        let hit = lines.iter().filter(|(_, count)| *count > 0).count();
+        let _ = writeln!(record, "LF:{}\nLH:{}\nend_of_record", lines.len(), hit);
+        record
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/* the nzp bits of a BR that may or may not be taken, None for anything else */
+fn conditional_branch(instruction: u16) -> Option<u16> {
+    let nzp = (instruction >> 9) & 0x7;
+    let is_br = instruction >> 12 == u16::from(OpCode::Br);
+    (is_br && nzp != 0 && nzp != 0x7).then_some(nzp)
+}
+
+/* the addresses and words of the image that are instructions */
+fn instructions<'a>(
+    image: &'a ObjectImage,
+    map: Option<&'a SourceMap>,
+) -> impl Iterator<Item = (u16, u16)> + 'a {
+    image
+        .words
+        .iter()
+        .enumerate()
+        .map(|(offset, &word)| (image.origin.wrapping_add(offset as u16), word))
+        .filter(move |&(address, _)| map.map_or(true, |map| map.line_at(address).is_some()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, Assembly};
+    use crate::vm::MemoryConsole;
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        BRn NEVER
+        HALT
+NEVER   ADD R0, R0, #1
+        HALT
+COUNT   .FILL #2
+        .END";
+
+    fn cover(source: &str) -> (VM, Assembly, Coverage) {
+        let assembly = assemble(source).unwrap();
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(&assembly.image);
+        vm.symbols_mut().extend(&assembly.symbols);
+        let mut coverage = Coverage::new();
+        vm.run_until(|vm, result| {
+            coverage.record(vm, result);
+            false
+        })
+        .unwrap();
+        (vm, assembly, coverage)
+    }
+
+    #[test]
+    fn test_counts_and_branches() {
+        let (_, _, coverage) = cover(PROGRAM);
+
+        assert_eq!(coverage.count_at(0x3001), 2);
+        assert_eq!(coverage.count_at(0x3005), 0);
+        assert_eq!(
+            coverage.branch_at(0x3002),
+            Some(BranchCoverage {
+                taken: 1,
+                not_taken: 1
+            })
+        );
+        assert_eq!(
+            coverage.branch_at(0x3003),
+            Some(BranchCoverage {
+                taken: 0,
+                not_taken: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let (_, assembly, coverage) = cover(PROGRAM);
+
+        let summary = coverage.summary(&assembly.image, Some(&assembly.source_map));
+        assert_eq!(
+            summary,
+            CoverageSummary {
+                instructions: 7,
+                executed: 5,
+                branch_outcomes: 4,
+                covered_outcomes: 3,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "5/7 instructions executed (71.43%), 3/4 branch outcomes (75.00%)"
+        );
+
+        /* without a source map the data word counts as well */
+        assert_eq!(coverage.summary(&assembly.image, None).instructions, 8);
+    }
+
+    #[test]
+    fn test_report() {
+        let (vm, assembly, coverage) = cover(PROGRAM);
+        let report = coverage.report(&vm, &assembly.image, Some(&assembly.source_map));
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "Coverage: 5/7 instructions executed (71.43%), 3/4 branch outcomes (75.00%)"
+        );
+        assert_eq!(
+            lines[4],
+            "         2  x3002  03FE              BRp LOOP    [taken 1, not taken 1]"
+        );
+        assert_eq!(
+            lines[7],
+            "     #####  x3005  1021  NEVER       ADD R0, R0, #1"
+        );
+        assert_eq!(lines[9], "         -  x3007  0002  COUNT       .FILL x0002");
+    }
+
+    #[test]
+    fn test_lcov() {
+        let (_, mut assembly, coverage) = cover(PROGRAM);
+        assembly.source_map.source = "count.asm".to_string();
+        let lcov = coverage.lcov(&assembly.image, &assembly.source_map);
+
+        assert_eq!(
+            lcov,
+            "TN:\nSF:count.asm\n\
+             BRDA:5,0,0,1\nBRDA:5,0,1,1\nBRDA:6,0,0,0\nBRDA:6,0,1,1\nBRF:4\nBRH:3\n\
+             DA:3,1\nDA:4,2\nDA:5,2\nDA:6,1\nDA:7,1\nDA:8,0\nDA:9,0\n\
+             LF:7\nLH:5\nend_of_record\n"
+        );
+    }
+}
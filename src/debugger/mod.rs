@@ -0,0 +1,878 @@
+// Module for the interactive command-line debugger
+
+use crate::asm::parse_number;
+use crate::disasm::{disassemble_word, DisasmLine};
+use crate::vm::{
+    ConditionFlag, MemoryWrite, OpCode, Privilege, Register, StepResult, StopReason, WatchKind,
+    Watchpoint, VM,
+};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+step [n]             execute one (or n) instructions (s)
+next                 like step, but runs JSR, JSRR and TRAP through to their return (n)
+continue             run until a breakpoint or until the program halts (c)
+reverse-step [n]     undo the last (or last n) instructions executed (rs)
+reverse-continue     run backwards until a breakpoint, a watched write or the start of the
+                     history (rc)
+history [n]          show how many instructions can be undone, or keep the last n
+break <addr|label>   set a breakpoint (b)
+delete [addr|label]  delete a breakpoint, or all of them (d)
+regs                 show the registers (r)
+mem <addr> [len]     show memory (x)
+set reg <reg> <val>  change a register (R0-R7, PC, COND, PSR)
+set mem <addr> <val> change a memory location
+watch [range] [kind] watch an address or range (x4000..x400F) for write, read or access,
+                     or list the watchpoints (w)
+unwatch [addr]       delete the watchpoints covering an address, or all of them
+disasm [addr] [len]  disassemble memory, from the PC by default
+help                 show this help (h)
+quit                 leave the debugger (q)
+An empty line repeats the previous command.";
+
+/// How many instructions the debugger can undo, unless the VM already keeps a history.
+pub const DEFAULT_HISTORY_LIMIT: usize = 10_000;
+
+const DEFAULT_DISASM_LEN: usize = 10;
+const DEFAULT_MEM_LEN: usize = 8;
+
+/// Whether the prompt loop should keep going after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+enum CommandError {
+    Io(io::Error),
+    /// A mistake in the command itself, reported to the user.
+    Usage(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> Self {
+        CommandError::Io(e)
+    }
+}
+
+type CommandResult = Result<Flow, CommandError>;
+
+fn usage(message: impl Into<String>) -> CommandError {
+    CommandError::Usage(message.into())
+}
+
+/// A debugger driving a VM one command at a time.
+pub struct Debugger<'a> {
+    vm: &'a mut VM,
+    breakpoints: BTreeSet<u16>,
+    last_command: String,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(vm: &'a mut VM) -> Self {
+        if vm.history_limit() == 0 {
+            vm.set_history_limit(DEFAULT_HISTORY_LIMIT);
+        }
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        self.vm
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input.
+    pub fn run<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        self.print_location(&mut output)?;
+        loop {
+            /* the program may have left the terminal in raw mode */
+            self.vm.console_mut().suspend()?;
+            write!(output, "(lc3db) ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
+            }
+            if self.execute(&line, &mut output)? == Flow::Quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one command line, writing what it has to say to `output`.
+    pub fn execute(&mut self, line: &str, output: &mut dyn Write) -> io::Result<Flow> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => {
+                self.last_command = line.to_string();
+                line.to_string()
+            }
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return Ok(Flow::Continue);
+        }
+
+        match self.dispatch(&args, output) {
+            Ok(flow) => Ok(flow),
+            Err(CommandError::Io(e)) => Err(e),
+            Err(CommandError::Usage(message)) => {
+                writeln!(output, "{}", message)?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+
+    fn dispatch(&mut self, args: &[&str], out: &mut dyn Write) -> CommandResult {
+        match args {
+            ["step" | "s"] => self.step(1, out),
+            ["step" | "s", count] => {
+                let count = self.parse_value(count)?;
+                self.step(count as usize, out)
+            }
+            ["next" | "n"] => self.next(out),
+            ["continue" | "c"] => self.cont(out),
+            ["reverse-step" | "rs"] => self.reverse_step(1, out),
+            ["reverse-step" | "rs", count] => {
+                let count = self.parse_value(count)?;
+                self.reverse_step(count as usize, out)
+            }
+            ["reverse-continue" | "rc"] => self.reverse_cont(out),
+            ["history"] => {
+                self.print_history(out)?;
+                Ok(Flow::Continue)
+            }
+            ["history", limit] => {
+                let limit = limit
+                    .parse()
+                    .map_err(|_| usage(format!("'{}' is not a number of instructions", limit)))?;
+                self.vm.set_history_limit(limit);
+                self.print_history(out)?;
+                Ok(Flow::Continue)
+            }
+            ["break" | "b", location] => {
+                let address = self.parse_address(location)?;
+                self.breakpoints.insert(address);
+                writeln!(
+                    out,
+                    "Breakpoint at {}",
+                    self.vm.symbols().format_address(address)
+                )?;
+                Ok(Flow::Continue)
+            }
+            ["delete" | "d"] => {
+                self.breakpoints.clear();
+                writeln!(out, "Deleted all breakpoints")?;
+                Ok(Flow::Continue)
+            }
+            ["delete" | "d", location] => {
+                let address = self.parse_address(location)?;
+                if !self.breakpoints.remove(&address) {
+                    return Err(usage(format!("No breakpoint at {}", location)));
+                }
+                Ok(Flow::Continue)
+            }
+            ["regs" | "r"] => {
+                self.print_registers(out)?;
+                Ok(Flow::Continue)
+            }
+            ["mem" | "x", address] => self.mem(address, DEFAULT_MEM_LEN, out),
+            ["mem" | "x", address, len] => {
+                let len = self.parse_value(len)?;
+                self.mem(address, len as usize, out)
+            }
+            ["set", "reg", register, value] if register.eq_ignore_ascii_case("psr") => {
+                let value = self.parse_value(value)?;
+                self.vm.set_psr(value);
+                Ok(Flow::Continue)
+            }
+            ["set", "reg", register, value] => {
+                let register = parse_register(register)
+                    .ok_or_else(|| usage(format!("Unknown register '{}'", register)))?;
+                let value = self.parse_value(value)?;
+                self.vm.set_reg(register, value);
+                Ok(Flow::Continue)
+            }
+            ["set", "mem", address, value] => {
+                let address = self.parse_address(address)?;
+                let value = self.parse_value(value)?;
+                self.vm.write_memory(address, value);
+                Ok(Flow::Continue)
+            }
+            ["disasm"] => self.disasm(self.vm.pc(), DEFAULT_DISASM_LEN, out),
+            ["disasm", address] => {
+                let address = self.parse_address(address)?;
+                self.disasm(address, DEFAULT_DISASM_LEN, out)
+            }
+            ["disasm", address, len] => {
+                let address = self.parse_address(address)?;
+                let len = self.parse_value(len)?;
+                self.disasm(address, len as usize, out)
+            }
+            ["watch" | "w"] => {
+                self.print_watchpoints(out)?;
+                Ok(Flow::Continue)
+            }
+            ["watch" | "w", range] => self.watch(range, WatchKind::Write, out),
+            ["watch" | "w", range, kind] => {
+                let kind = match *kind {
+                    "read" => WatchKind::Read,
+                    "write" => WatchKind::Write,
+                    "access" => WatchKind::Access,
+                    _ => return Err(usage("Expected 'read', 'write' or 'access'")),
+                };
+                self.watch(range, kind, out)
+            }
+            ["unwatch"] => {
+                self.vm.clear_watchpoints();
+                writeln!(out, "Deleted all watchpoints")?;
+                Ok(Flow::Continue)
+            }
+            ["unwatch", location] => {
+                let address = self.parse_address(location)?;
+                if self.vm.remove_watchpoints_at(address) == 0 {
+                    return Err(usage(format!("No watchpoint at {}", location)));
+                }
+                Ok(Flow::Continue)
+            }
+            ["help" | "h"] => {
+                writeln!(out, "{}", HELP)?;
+                Ok(Flow::Continue)
+            }
+            ["quit" | "q"] => Ok(Flow::Quit),
+            _ => Err(usage(format!(
+                "Unknown command '{}', try 'help'",
+                args.join(" ")
+            ))),
+        }
+    }
+
+    fn step(&mut self, count: usize, out: &mut dyn Write) -> CommandResult {
+        for _ in 0..count {
+            if !self.ensure_running(out)? {
+                break;
+            }
+            match self.vm.step() {
+                Ok(result) => {
+                    if count == 1 {
+                        self.print_effects(&result, out)?;
+                    }
+                    if !result.watch_hits.is_empty() {
+                        self.print_watch_hits(&result, out)?;
+                        break;
+                    }
+                    if result.halted {
+                        writeln!(out, "The program halted")?;
+                        return Ok(Flow::Continue);
+                    }
+                }
+                Err(e) => {
+                    self.print_fault(&e, out)?;
+                    return Ok(Flow::Continue);
+                }
+            }
+        }
+        self.print_location(out)?;
+        Ok(Flow::Continue)
+    }
+
+    fn next(&mut self, out: &mut dyn Write) -> CommandResult {
+        if !self.ensure_running(out)? {
+            return Ok(Flow::Continue);
+        }
+        let pc = self.vm.pc();
+        let op = OpCode::try_from(self.vm.read_memory(pc) >> 12).unwrap();
+        if op != OpCode::Jsr && op != OpCode::Trap {
+            return self.step(1, out);
+        }
+        /* run the subroutine or trap routine until it returns to the next instruction */
+        self.run_to(Some(pc.wrapping_add(1)), out)
+    }
+
+    fn cont(&mut self, out: &mut dyn Write) -> CommandResult {
+        if !self.ensure_running(out)? {
+            return Ok(Flow::Continue);
+        }
+        self.run_to(None, out)
+    }
+
+    /* runs until `stop_at`, a breakpoint, a watchpoint or the end of the program */
+    fn run_to(&mut self, stop_at: Option<u16>, out: &mut dyn Write) -> CommandResult {
+        let breakpoints = &self.breakpoints;
+        let mut watched = None;
+        let result = self.vm.run_until(|vm, result| {
+            if !result.watch_hits.is_empty() {
+                watched = Some(result.clone());
+            }
+            Some(vm.pc()) == stop_at || breakpoints.contains(&vm.pc())
+        });
+        if let Some(watched) = watched {
+            self.vm.console_mut().suspend()?;
+            self.print_watch_hits(&watched, out)?;
+        }
+        self.report_stop(result, out)
+    }
+
+    fn report_stop(
+        &mut self,
+        result: Result<StopReason, crate::vm::VmError>,
+        out: &mut dyn Write,
+    ) -> CommandResult {
+        self.vm.console_mut().suspend()?;
+        match result {
+            /* the stopping predicate may also hold on the instruction that halted */
+            Ok(StopReason::Halted) => writeln!(out, "The program halted")?,
+            Ok(_) if !self.vm.is_running() => writeln!(out, "The program halted")?,
+            Ok(_) => {
+                let pc = self.vm.pc();
+                if self.breakpoints.contains(&pc) {
+                    writeln!(
+                        out,
+                        "Breakpoint at {}",
+                        self.vm.symbols().format_address(pc)
+                    )?;
+                }
+                self.print_location(out)?;
+            }
+            Err(e) => self.print_fault(&e, out)?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn reverse_step(&mut self, count: usize, out: &mut dyn Write) -> CommandResult {
+        for _ in 0..count {
+            match self.vm.step_back() {
+                Some(writes) => {
+                    if self.print_undone_watch_hits(&writes, out)? {
+                        break;
+                    }
+                }
+                None => {
+                    writeln!(out, "No more history")?;
+                    break;
+                }
+            }
+        }
+        self.print_location(out)?;
+        Ok(Flow::Continue)
+    }
+
+    /* runs backwards until a breakpoint, a watched write or the start of the history */
+    fn reverse_cont(&mut self, out: &mut dyn Write) -> CommandResult {
+        loop {
+            let Some(writes) = self.vm.step_back() else {
+                writeln!(out, "Reached the start of the history")?;
+                break;
+            };
+            if self.print_undone_watch_hits(&writes, out)? {
+                break;
+            }
+            let pc = self.vm.pc();
+            if self.breakpoints.contains(&pc) {
+                writeln!(
+                    out,
+                    "Breakpoint at {}",
+                    self.vm.symbols().format_address(pc)
+                )?;
+                break;
+            }
+        }
+        self.print_location(out)?;
+        Ok(Flow::Continue)
+    }
+
+    /* reports the undone writes that trigger a watchpoint, returning whether there were any */
+    fn print_undone_watch_hits(
+        &self,
+        writes: &[MemoryWrite],
+        out: &mut dyn Write,
+    ) -> io::Result<bool> {
+        let symbols = self.vm.symbols();
+        let pc = self.vm.pc();
+        let instruction = disassemble_word(pc, self.vm.read_memory(pc), symbols);
+        let mut hit = false;
+        for write in writes {
+            let watched = self
+                .vm
+                .watchpoints()
+                .iter()
+                .any(|watchpoint| watchpoint.triggers(write.address, true));
+            if watched {
+                writeln!(
+                    out,
+                    "Watchpoint: {} ({}) wrote {}: x{:04X} -> x{:04X}",
+                    symbols.format_address(pc),
+                    instruction,
+                    symbols.format_address(write.address),
+                    write.old,
+                    write.new
+                )?;
+                hit = true;
+            }
+        }
+        Ok(hit)
+    }
+
+    fn print_history(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "History: {} of {} instructions",
+            self.vm.history_len(),
+            self.vm.history_limit()
+        )
+    }
+
+    fn ensure_running(&self, out: &mut dyn Write) -> io::Result<bool> {
+        if !self.vm.is_running() {
+            writeln!(out, "The program is not running")?;
+        }
+        Ok(self.vm.is_running())
+    }
+
+    fn watch(&mut self, range: &str, kind: WatchKind, out: &mut dyn Write) -> CommandResult {
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (self.parse_address(start)?, self.parse_address(end)?),
+            None => {
+                let address = self.parse_address(range)?;
+                (address, address)
+            }
+        };
+        if end < start {
+            return Err(usage(format!("'{}' is an empty range", range)));
+        }
+        let watchpoint = Watchpoint::new(start, end, kind);
+        self.vm.add_watchpoint(watchpoint);
+        self.print_watchpoint(&watchpoint, out)?;
+        Ok(Flow::Continue)
+    }
+
+    fn print_watchpoints(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.vm.watchpoints().is_empty() {
+            writeln!(out, "No watchpoints")?;
+        }
+        for watchpoint in self.vm.watchpoints() {
+            self.print_watchpoint(watchpoint, out)?;
+        }
+        Ok(())
+    }
+
+    fn print_watchpoint(&self, watchpoint: &Watchpoint, out: &mut dyn Write) -> io::Result<()> {
+        let symbols = self.vm.symbols();
+        let kind = match watchpoint.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        };
+        if watchpoint.start == watchpoint.end {
+            writeln!(
+                out,
+                "Watchpoint ({}) at {}",
+                kind,
+                symbols.format_address(watchpoint.start)
+            )
+        } else {
+            writeln!(
+                out,
+                "Watchpoint ({}) at {}..{}",
+                kind,
+                symbols.format_address(watchpoint.start),
+                symbols.format_address(watchpoint.end)
+            )
+        }
+    }
+
+    fn mem(&mut self, address: &str, len: usize, out: &mut dyn Write) -> CommandResult {
+        let start = self.parse_address(address)?;
+        for row in 0..(len + DEFAULT_MEM_LEN - 1) / DEFAULT_MEM_LEN {
+            let row_start = start.wrapping_add((row * DEFAULT_MEM_LEN) as u16);
+            write!(out, "x{:04X}:", row_start)?;
+            for i in 0..DEFAULT_MEM_LEN.min(len - row * DEFAULT_MEM_LEN) {
+                let value = self.vm.read_memory(row_start.wrapping_add(i as u16));
+                write!(out, " {:04X}", value)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn disasm(&mut self, start: u16, len: usize, out: &mut dyn Write) -> CommandResult {
+        let pc = self.vm.pc();
+        for line in self.disassemble(start, len) {
+            let breakpoint = if self.breakpoints.contains(&line.address) {
+                '*'
+            } else {
+                ' '
+            };
+            let current = if line.address == pc { '>' } else { ' ' };
+            writeln!(out, "{}{} {}", breakpoint, current, line)?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn print_location(&self, out: &mut dyn Write) -> io::Result<()> {
+        let line = &self.disassemble(self.vm.pc(), 1)[0];
+        writeln!(out, "=> {}", line)
+    }
+
+    /* like disasm::disassemble, but device registers show what the program would read */
+    fn disassemble(&self, start: u16, len: usize) -> Vec<DisasmLine> {
+        (0..len)
+            .map(|i| {
+                let address = start.wrapping_add(i as u16);
+                DisasmLine::new(address, self.vm.read_memory(address), self.vm.symbols())
+            })
+            .collect()
+    }
+
+    fn print_effects(&self, result: &StepResult, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(vector) = result.interrupt {
+            writeln!(out, "   interrupt x{:02X} taken", vector)?;
+        }
+        if let Some(vector) = result.exception {
+            writeln!(out, "   exception x{:02X} raised", vector)?;
+        }
+        for write in &result.register_writes {
+            if write.register == Register::Cond {
+                writeln!(
+                    out,
+                    "   COND {} -> {}",
+                    flag_name(write.old),
+                    flag_name(write.new)
+                )?;
+            } else {
+                writeln!(
+                    out,
+                    "   {:?} x{:04X} -> x{:04X}",
+                    write.register, write.old, write.new
+                )?;
+            }
+        }
+        for write in &result.memory_writes {
+            writeln!(
+                out,
+                "   [{}] x{:04X} -> x{:04X}",
+                self.vm.symbols().format_address(write.address),
+                write.old,
+                write.new
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_watch_hits(&self, result: &StepResult, out: &mut dyn Write) -> io::Result<()> {
+        let symbols = self.vm.symbols();
+        let instruction = disassemble_word(result.pc_before, result.instruction, symbols);
+        for hit in &result.watch_hits {
+            write!(
+                out,
+                "Watchpoint: {} ({}) ",
+                symbols.format_address(result.pc_before),
+                instruction
+            )?;
+            let address = symbols.format_address(hit.address);
+            match hit.kind {
+                WatchKind::Write => writeln!(
+                    out,
+                    "wrote {}: x{:04X} -> x{:04X}",
+                    address, hit.old, hit.new
+                )?,
+                _ => writeln!(out, "read {}: x{:04X}", address, hit.new)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn print_fault(&self, e: &crate::vm::VmError, out: &mut dyn Write) -> io::Result<()> {
+        match e.pc() {
+            Some(pc) => writeln!(
+                out,
+                "Fault: {} ({})",
+                e,
+                self.vm.symbols().format_address(pc)
+            ),
+            None => writeln!(out, "Fault: {}", e),
+        }
+    }
+
+    fn print_registers(&self, out: &mut dyn Write) -> io::Result<()> {
+        for row in 0..2 {
+            let line: Vec<String> = (0..4)
+                .map(|i| {
+                    let index = row * 4 + i;
+                    let register = Register::try_from(index).unwrap();
+                    format!("R{} x{:04X}", index, self.vm.reg(register))
+                })
+                .collect();
+            writeln!(out, "{}", line.join("  "))?;
+        }
+        writeln!(
+            out,
+            "PC x{:04X} ({})  COND {}",
+            self.vm.pc(),
+            self.vm.symbols().format_address(self.vm.pc()),
+            flag_name(self.vm.reg(Register::Cond))
+        )?;
+        let mode = match self.vm.privilege() {
+            Privilege::User => "user",
+            Privilege::Supervisor => "supervisor",
+        };
+        writeln!(
+            out,
+            "PSR x{:04X} ({}, priority {})  saved USP x{:04X}  saved SSP x{:04X}",
+            self.vm.psr(),
+            mode,
+            self.vm.priority(),
+            self.vm.saved_usp(),
+            self.vm.saved_ssp()
+        )
+    }
+
+    /* an address given as a number, a label or a label plus an offset (LOOP+3) */
+    fn parse_address(&self, text: &str) -> Result<u16, CommandError> {
+        if let Some((label, offset)) = text.split_once('+') {
+            if let (Some(address), Some(offset)) =
+                (self.vm.symbols().address_of(label), parse_number(offset))
+            {
+                return Ok(address.wrapping_add(offset as u16));
+            }
+        }
+        self.parse_value(text)
+    }
+
+    /* a word given as a number (x3000, #-1, 12) or a label */
+    fn parse_value(&self, text: &str) -> Result<u16, CommandError> {
+        match parse_number(text) {
+            Some(value) if (-0x8000..=0xFFFF).contains(&value) => Ok(value as u16),
+            Some(_) => Err(usage(format!("'{}' does not fit in a word", text))),
+            None => self
+                .vm
+                .symbols()
+                .address_of(text)
+                .ok_or_else(|| usage(format!("'{}' is neither a number nor a label", text))),
+        }
+    }
+}
+
+fn parse_register(text: &str) -> Option<Register> {
+    match text.to_uppercase().as_str() {
+        "PC" => Some(Register::PC),
+        "COND" => Some(Register::Cond),
+        name => {
+            let index = name.strip_prefix('R')?.parse::<usize>().ok()?;
+            if index < 8 {
+                Register::try_from(index).ok()
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn flag_name(value: u16) -> &'static str {
+    match ConditionFlag::try_from(value) {
+        Ok(ConditionFlag::Neg) => "N",
+        Ok(ConditionFlag::Zro) => "Z",
+        Ok(ConditionFlag::Pos) => "P",
+        Err(_) => "?",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::MemoryConsole;
+
+    fn load(vm: &mut VM, source: &str) {
+        let assembly = assemble(source).unwrap();
+        vm.load_object(&assembly.image);
+        vm.symbols_mut().extend(&assembly.symbols);
+    }
+
+    fn run_commands(debugger: &mut Debugger, commands: &[&str]) -> String {
+        let mut output = Vec::new();
+        for command in commands {
+            debugger.execute(command, &mut output).unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    const PROGRAM: &str = "
+            .ORIG x3000
+            AND R1, R1, #0
+            JSR INC
+            JSR INC
+            OUT
+            HALT
+    INC     ADD R1, R1, #1
+            ST R1, COUNT
+            RET
+    COUNT   .FILL 0
+            .END
+    ";
+
+    #[test]
+    fn test_step_and_registers() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+
+        let output = run_commands(&mut debugger, &["step", "", "regs"]);
+
+        assert!(output.contains("   R1 x0000 -> x0000\n   COND Z -> Z\n=> x3001"));
+        assert!(output.contains("=> x3001  4803              JSR INC\n"));
+        assert!(output.contains("   R7 x0000 -> x3002\n"));
+        assert!(output.contains("=> x3005  1261  INC         ADD R1, R1, #1\n"));
+        assert!(output.contains("R4 x0000  R5 x0000  R6 x0000  R7 x3002\n"));
+        assert!(output.contains("PC x3005 (INC)  COND Z\n"));
+        assert!(output.contains("PSR x8002 (user, priority 0)  saved USP x0000  saved SSP x3000\n"));
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+
+        let output = run_commands(&mut debugger, &["break INC+1", "c", "c", "mem COUNT 1"]);
+
+        assert!(output.contains("Breakpoint at INC+1\n"));
+        assert_eq!(output.matches("Breakpoint at INC+1\n=> x3006").count(), 2);
+        assert!(output.ends_with("x3008: 0001\n"));
+
+        let output = run_commands(&mut debugger, &["delete", "continue", "step"]);
+        assert!(output.contains("The program halted\n"));
+        assert!(output.contains("The program is not running\n"));
+        assert_eq!(debugger.vm().reg(Register::R1), 2);
+    }
+
+    #[test]
+    fn test_next_steps_over_subroutines() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+        debugger.vm.set_reg(Register::R0, 'A' as u16);
+
+        let output = run_commands(&mut debugger, &["n", "n", "n", "n"]);
+
+        assert!(output.ends_with("=> x3004  F025              HALT\n"));
+        assert_eq!(debugger.vm().reg(Register::R1), 2);
+        assert_eq!(console.output_string(), "A");
+    }
+
+    #[test]
+    fn test_set_and_disasm() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+
+        let output = run_commands(
+            &mut debugger,
+            &[
+                "set reg R2 #-1",
+                "set mem COUNT x1234",
+                "set reg pc INC",
+                "b x3006",
+                "disasm INC 3",
+                "set reg R9 1",
+                "bogus",
+            ],
+        );
+
+        assert_eq!(debugger.vm().reg(Register::R2), 0xFFFF);
+        assert_eq!(debugger.vm().read_memory(0x3008), 0x1234);
+        assert_eq!(debugger.vm().pc(), 0x3005);
+        assert!(output.contains(" > x3005  1261  INC         ADD R1, R1, #1\n"));
+        assert!(output.contains("*  x3006  3201              ST R1, COUNT\n"));
+        assert!(output.contains("Unknown register 'R9'\n"));
+        assert!(output.contains("Unknown command 'bogus', try 'help'\n"));
+
+        /* device registers show their value, not the memory behind them */
+        let output = run_commands(&mut debugger, &["set reg pc xFE04", "disasm"]);
+        assert!(output.starts_with(" > xFE04  8000              RTI\n"));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+
+        let output = run_commands(&mut debugger, &["watch COUNT", "c", "c"]);
+
+        assert!(output.starts_with("Watchpoint (write) at COUNT\n"));
+        assert!(output.contains(
+            "Watchpoint: INC+1 (ST R1, COUNT) wrote COUNT: x0000 -> x0001\n\
+             => x3007  C1C0              RET\n"
+        ));
+        assert!(output.contains("wrote COUNT: x0001 -> x0002\n"));
+
+        let output = run_commands(
+            &mut debugger,
+            &["unwatch COUNT", "w x3000..x3001 read", "w", "c"],
+        );
+        assert!(output.contains("Watchpoint (read) at x3000..x3001\n"));
+        assert!(output.ends_with("The program halted\n"));
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+
+        let output = run_commands(&mut debugger, &["c", "b INC", "rc", "regs"]);
+        assert!(output.contains("The program halted\n"));
+        assert!(output.contains("Breakpoint at INC\n=> x3005  1261  INC         ADD R1, R1, #1\n"));
+        assert!(output.contains("R4 x0000  R5 x0000  R6 x0000  R7 x3003\n"));
+        assert_eq!(debugger.vm().reg(Register::R1), 1);
+        assert_eq!(debugger.vm().read_memory(0x3008), 1);
+
+        let output = run_commands(&mut debugger, &["delete", "watch COUNT", "rc"]);
+        assert!(output.ends_with(
+            "Watchpoint: INC+1 (ST R1, COUNT) wrote COUNT: x0000 -> x0001\n\
+             => x3006  3201              ST R1, COUNT\n"
+        ));
+        assert_eq!(debugger.vm().read_memory(0x3008), 0);
+
+        let output = run_commands(&mut debugger, &["rs 10", "history"]);
+        assert!(output.contains("No more history\n=> x3000  5260              AND R1, R1, #0\n"));
+        assert!(output.ends_with("History: 0 of 10000 instructions\n"));
+
+        /* the program runs forward again from where it went back to */
+        let output = run_commands(&mut debugger, &["unwatch", "c", "history 3"]);
+        assert!(output.contains("The program halted\n"));
+        assert!(output.ends_with("History: 3 of 3 instructions\n"));
+        assert_eq!(debugger.vm().reg(Register::R1), 2);
+        /* console output is not undone, so it was written twice */
+        assert_eq!(console.output_string().matches("Halting the VM").count(), 2);
+    }
+
+    #[test]
+    fn test_run_reads_commands_until_quit() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        load(&mut vm, PROGRAM);
+        let mut debugger = Debugger::new(&mut vm);
+        let mut output = Vec::new();
+
+        debugger
+            .run("step\nquit\nstep\n".as_bytes(), &mut output)
+            .unwrap();
+
+        assert_eq!(debugger.vm().pc(), 0x3001);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("=> x3000  5260              AND R1, R1, #0\n(lc3db) "));
+    }
+}
@@ -0,0 +1,249 @@
+// Module for the LC-3 disassembler, turning instruction words back into assembly
+
+use crate::vm::{ObjectImage, OpCode, SymbolTable, TrapCode, VM};
+use std::fmt;
+
+/// One disassembled memory location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub address: u16,
+    pub word: u16,
+    /// The label defined at `address`, if any.
+    pub label: Option<String>,
+    /// The instruction in assembly syntax, or a `.FILL` for words that are not instructions.
+    pub text: String,
+}
+
+impl DisasmLine {
+    /// Disassembles `word`, found at `address`.
+    pub fn new(address: u16, word: u16, symbols: &SymbolTable) -> Self {
+        DisasmLine {
+            address,
+            word,
+            label: symbols.label_at(address).map(str::to_string),
+            text: disassemble_word(address, word, symbols),
+        }
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X}  {:04X}  {:<12}{}",
+            self.address,
+            self.word,
+            self.label.as_deref().unwrap_or(""),
+            self.text
+        )
+    }
+}
+
+/// Disassembles `len` words of `memory` starting at `start`, wrapping around at the end of memory.
+pub fn disassemble(
+    memory: &[u16],
+    start: u16,
+    len: usize,
+    symbols: &SymbolTable,
+) -> Vec<DisasmLine> {
+    (0..len)
+        .map(|i| {
+            let address = start.wrapping_add(i as u16);
+            DisasmLine::new(address, memory[address as usize], symbols)
+        })
+        .collect()
+}
+
+/// Disassembles every word of an object image.
+pub fn disassemble_image(image: &ObjectImage, symbols: &SymbolTable) -> Vec<DisasmLine> {
+    let mut memory = vec![0; image.origin as usize];
+    memory.extend_from_slice(&image.words);
+    disassemble(&memory, image.origin, image.words.len(), symbols)
+}
+
+/// Disassembles the instruction `word` found at `address`.
+///
+/// PC-relative operands are resolved to their target address, named after
+/// the closest label when `symbols` has one.
+pub fn disassemble_word(address: u16, word: u16, symbols: &SymbolTable) -> String {
+    decode(address, word, symbols).unwrap_or_else(|| format!(".FILL x{:04X}", word))
+}
+
+/* returns None for words which are not valid instructions */
+fn decode(address: u16, instr: u16, symbols: &SymbolTable) -> Option<String> {
+    let op = OpCode::try_from(instr >> 12).ok()?;
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let target = |bits: u16| {
+        let offset = VM::sign_extend(instr & ((1 << bits) - 1), bits);
+        symbols.format_address(address.wrapping_add(1).wrapping_add(offset))
+    };
+    let imm = |bits: u16| VM::sign_extend(instr & ((1 << bits) - 1), bits) as i16;
+
+    Some(match op {
+        OpCode::Add | OpCode::And => {
+            /*
+                15 14 13 12 | 11 10 9 | 8 7 6 | 5 | 4 3 2 1 0
+                    0 0 0 1 |   DR    |  SR1  | 0 | 0 0 | SR2
+                    0 0 0 1 |   DR    |  SR1  | 1 |   imm5
+            */
+            let name = if op == OpCode::Add { "ADD" } else { "AND" };
+            if (instr >> 5) & 0x1 != 0 {
+                format!("{} R{}, R{}, #{}", name, dr, sr1, imm(5))
+            } else if (instr >> 3) & 0x3 == 0 {
+                format!("{} R{}, R{}, R{}", name, dr, sr1, instr & 0x7)
+            } else {
+                return None;
+            }
+        }
+        OpCode::Not => {
+            if instr & 0x3F != 0x3F {
+                return None;
+            }
+            format!("NOT R{}, R{}", dr, sr1)
+        }
+        OpCode::Br => {
+            /*
+               15 14 13 12 | 11  10  9 | 8 7 6 5 4 3 2 1 0
+                   0 0 0 0 | n | z | p |  PCoffset9
+            */
+            let n = if (instr >> 11) & 0x1 != 0 { "n" } else { "" };
+            let z = if (instr >> 10) & 0x1 != 0 { "z" } else { "" };
+            let p = if (instr >> 9) & 0x1 != 0 { "p" } else { "" };
+            /* a branch on no condition never branches, it is most likely data */
+            if dr == 0 {
+                return None;
+            }
+            format!("BR{}{}{} {}", n, z, p, target(9))
+        }
+        OpCode::Jmp => {
+            if dr != 0 || instr & 0x3F != 0 {
+                return None;
+            }
+            if sr1 == 7 {
+                "RET".to_string()
+            } else {
+                format!("JMP R{}", sr1)
+            }
+        }
+        OpCode::Jsr => {
+            /*
+                    15 14 13 12 | 11 | 10 9 8 7 6 | 5 4 3 2 1 0
+                JSR     0 1 0 0 |  1 |      PCoffset11
+                JSRR    0 1 0 0 |  0 | 0 0 | BaseR | 0 0 0 0 0 0
+            */
+            if (instr >> 11) & 0x1 != 0 {
+                format!("JSR {}", target(11))
+            } else if (instr >> 9) & 0x3 == 0 && instr & 0x3F == 0 {
+                format!("JSRR R{}", sr1)
+            } else {
+                return None;
+            }
+        }
+        OpCode::Ld => format!("LD R{}, {}", dr, target(9)),
+        OpCode::Ldi => format!("LDI R{}, {}", dr, target(9)),
+        OpCode::Lea => format!("LEA R{}, {}", dr, target(9)),
+        OpCode::St => format!("ST R{}, {}", dr, target(9)),
+        OpCode::Sti => format!("STI R{}, {}", dr, target(9)),
+        OpCode::Ldr => format!("LDR R{}, R{}, #{}", dr, sr1, imm(6)),
+        OpCode::Str => format!("STR R{}, R{}, #{}", dr, sr1, imm(6)),
+        OpCode::Trap => {
+            /*
+                15 14 13 12 | 11 10 9 8 7 6 5 4 3 2 1 0
+                    1 1 1 1 | 0 0 0 0 |   trapvect8
+            */
+            if (instr >> 8) & 0xF != 0 {
+                return None;
+            }
+            match TrapCode::try_from(instr & 0xFF) {
+                Ok(TrapCode::Getc) => "GETC".to_string(),
+                Ok(TrapCode::Out) => "OUT".to_string(),
+                Ok(TrapCode::Puts) => "PUTS".to_string(),
+                Ok(TrapCode::In) => "IN".to_string(),
+                Ok(TrapCode::Putsp) => "PUTSP".to_string(),
+                Ok(TrapCode::Halt) => "HALT".to_string(),
+                Err(_) => format!("TRAP x{:02X}", instr & 0xFF),
+            }
+        }
+        OpCode::Rti => {
+            if instr & 0x0FFF != 0 {
+                return None;
+            }
+            "RTI".to_string()
+        }
+        OpCode::Res => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_disassemble_word() {
+        let symbols = SymbolTable::new();
+        let cases = [
+            (0x1283, "ADD R1, R2, R3"),
+            (0x12BF, "ADD R1, R2, #-1"),
+            (0x5020, "AND R0, R0, #0"),
+            (0x997F, "NOT R4, R5"),
+            (0x0FFB, "BRnzp x2FFC"),
+            (0x0402, "BRz x3003"),
+            (0xC080, "JMP R2"),
+            (0xC1C0, "RET"),
+            (0x4802, "JSR x3003"),
+            (0x40C0, "JSRR R3"),
+            (0x2008, "LD R0, x3009"),
+            (0x65BE, "LDR R2, R6, #-2"),
+            (0xE607, "LEA R3, x3008"),
+            (0x7583, "STR R2, R6, #3"),
+            (0xF025, "HALT"),
+            (0xF026, "TRAP x26"),
+            (0x8000, "RTI"),
+            (0xD000, ".FILL xD000"),
+            (0x0000, ".FILL x0000"),
+            (0x1288, ".FILL x1288"),
+        ];
+        for (word, text) in cases {
+            assert_eq!(disassemble_word(0x3000, word, &symbols), text);
+        }
+    }
+
+    #[test]
+    fn test_disassemble_round_trip_with_symbols() {
+        let source = "
+            .ORIG x3000
+    START   LEA R0, MSG
+            PUTS
+    LOOP    ADD R1, R1, #-1
+            BRp LOOP
+            JSR START
+            HALT
+    MSG     .STRINGZ \"A\"
+            .END
+        ";
+        let assembly = assemble(source).unwrap();
+        let lines = disassemble_image(&assembly.image, &assembly.symbols);
+
+        let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "LEA R0, MSG",
+                "PUTS",
+                "ADD R1, R1, #-1",
+                "BRp LOOP",
+                "JSR START",
+                "HALT",
+                ".FILL x0041",
+                ".FILL x0000",
+            ]
+        );
+        assert_eq!(lines[2].label.as_deref(), Some("LOOP"));
+        assert_eq!(
+            lines[2].to_string(),
+            "x3002  127F  LOOP        ADD R1, R1, #-1"
+        );
+    }
+}
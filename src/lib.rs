@@ -0,0 +1,18 @@
+pub mod asm;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod os;
+pub mod profile;
+pub mod trace;
+/* only public for the lc3 binary's Ctrl-C handling, not part of the library */
+#[doc(hidden)]
+pub mod utils;
+pub mod vm;
+
+pub use vm::{
+    ConditionFlag, Console, Device, DeviceContext, ExceptionMode, InputEvent, InputLog, Interrupt,
+    Limit, Limits, MemoryConsole, MemoryMappedRegister, MemoryRead, MemoryWrite, ObjectImage,
+    OpCode, Privilege, Register, RegisterWrite, StepResult, StopReason, StreamConsole, SymbolTable,
+    TerminalConsole, TrapCode, TrapMode, VmError, WatchHit, WatchKind, Watchpoint, VM,
+};
@@ -1,30 +1,520 @@
-mod utils;
-mod vm;
-
+use lc3_vm::{
+    asm::{self, SourceMap},
+    coverage::Coverage,
+    debugger::Debugger,
+    disasm, os,
+    profile::Profiler,
+    trace::{TraceFilter, Tracer},
+    utils, ExceptionMode, InputLog, Limit, Limits, ObjectImage, StreamConsole, SymbolTable,
+    TrapMode, VmError, VM,
+};
 use std::env;
-use vm::VM;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
-fn main() {
-    utils::terminal::spawn_control_c_handler().unwrap();
+const USAGE: &str = "usage:
+  lc3 [run] [--debug] [--os | --os=<os.obj>] [--start <address>] [batch options] [image-file1] ...
+  lc3 [run] --resume <snapshot.lc3s> [options]     (no images, --os or --start: the snapshot has them)
+  lc3 asm <source.asm> [-o <image.obj>]     (also writes <image.sym> and <image.map>)
+  lc3 disasm <image.obj> ...
+
+The program starts at the origin of the first image, or at the address given with --start
+(e.g. --start x3000, where every program used to start).
+
+batch options, to run without a terminal:
+  --no-tty                   read the program's input from stdin, never touch the terminal
+  --input <file>             read the program's input from a file (implies --no-tty)
+  --input-string <text>      use <text> as the program's input (implies --no-tty)
+  --output <file>            write the program's output to a file (implies --no-tty)
+
+debugging:
+  --history <n>              with --debug, how many instructions reverse-step can undo
+                             (default 10000)
+
+tracing:
+  --trace <file>             write every executed instruction to <file>, as JSON lines
+  --trace-range <a>..<b>     only trace the instructions at addresses a to b
+  --trace-op <op>,...        only trace these opcodes (e.g. ADD,LDR,TRAP)
+
+profiling:
+  --profile                  print where the program spent its instructions when it stops
+
+coverage:
+  --coverage <file>          write which instructions of the images were executed to <file>
+  --lcov <file>              write the coverage of the images' source lines to <file>, in the
+                             lcov format (needs the <image.map> written by lc3 asm)
+
+limits, to run untrusted programs:
+  --timeout <seconds>        stop the program if it runs for longer
+  --max-instructions <n>     stop the program after <n> instructions
+  --max-output <bytes>       stop the program once it writes more output
+
+snapshots:
+  --save-on-halt <file>      save the whole machine to <file> when the program stops for any
+                             reason, including a fault, a limit or Ctrl-C
+  --resume <file>            carry on from a snapshot saved with --save-on-halt
+
+record and replay:
+  --record <file>            log every byte of input with the instruction it was read at
+  --replay <file>            feed the program the input of a --record log at the same points
+
+exit status: 0 halted, 1 fault, 2 usage, 3 timeout, 4 instruction limit, 5 output limit,
+             130 interrupted by Ctrl-C";
 
+fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("lc3 [image-file1] ...");
-        std::process::exit(2);
+    match args.get(1).map(String::as_str) {
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+        Some("asm") => assemble(&args[2..]),
+        Some("disasm") => disassemble(&args[2..]),
+        Some("run") => run(&args[2..]),
+        Some(_) => run(&args[1..]),
     }
+}
+
+fn assemble(args: &[String]) {
+    let (source_path, output_path) = match args {
+        [source] => (source, Path::new(source).with_extension("obj")),
+        [source, flag, output] if flag == "-o" => (source, output.into()),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let source = fs::read_to_string(source_path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", source_path, e);
+        std::process::exit(1);
+    });
+    let assembly = asm::assemble(&source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", source_path, e);
+        std::process::exit(1);
+    });
+    let output = output_path.to_string_lossy();
+    if let Err(e) = assembly.image.write(&output) {
+        eprintln!("failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+    let sym_path = output_path.with_extension("sym");
+    let sym_output = sym_path.to_string_lossy();
+    if let Err(e) = assembly.symbols.write(&sym_output) {
+        eprintln!("failed to write {}: {}", sym_output, e);
+        std::process::exit(1);
+    }
+    let map_path = output_path.with_extension("map");
+    let map_output = map_path.to_string_lossy();
+    let mut source_map = assembly.source_map;
+    source_map.source = source_path.clone();
+    if let Err(e) = source_map.write(&map_output) {
+        eprintln!("failed to write {}: {}", map_output, e);
+        std::process::exit(1);
+    }
+}
 
-    let mut vm = VM::new();
+fn disassemble(images: &[String]) {
+    if images.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
 
-    for arg in &args[1..] {
-        if let Err(e) = vm.load_image(arg) {
-            eprintln!("failed to load image: {}: {}", arg, e);
+    for path in images {
+        let image = ObjectImage::read(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
             std::process::exit(1);
+        });
+        let sym_path = Path::new(path).with_extension("sym");
+        let symbols = if sym_path.is_file() {
+            SymbolTable::read(&sym_path.to_string_lossy()).unwrap_or_else(|e| {
+                eprintln!("failed to load symbols: {}: {}", sym_path.display(), e);
+                std::process::exit(1);
+            })
+        } else {
+            SymbolTable::new()
+        };
+
+        if images.len() > 1 {
+            println!("; {}", path);
+        }
+        for line in disasm::disassemble_image(&image, &symbols) {
+            println!("{}", line);
         }
     }
+}
+
+/* exit statuses of a run */
+const EXIT_FAULT: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
+const EXIT_INSTRUCTION_LIMIT: i32 = 4;
+const EXIT_OUTPUT_LIMIT: i32 = 5;
+const EXIT_INTERRUPTED: i32 = 130;
 
-    vm.run();
+/* how many addresses and subroutines the profile report ranks */
+const PROFILE_TOP: usize = 20;
+
+/* the OS to run traps and exceptions through */
+enum Os {
+    Bundled,
+    Image(String),
+}
+
+/* where a batch run reads the program's input from */
+enum Input {
+    Stdin,
+    File(String),
+    Text(String),
+}
+
+#[derive(Default)]
+struct RunOptions {
+    debug: bool,
+    os: Option<Os>,
+    /* set for batch runs, which never touch the terminal */
+    input: Option<Input>,
+    output: Option<String>,
+    limits: Limits,
+    trace: Option<String>,
+    trace_filter: TraceFilter,
+    profile: bool,
+    coverage: Option<String>,
+    lcov: Option<String>,
+    save_on_halt: Option<String>,
+    resume: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    history: Option<usize>,
+    start: Option<u16>,
+    images: Vec<String>,
+}
 
-    utils::terminal::restore_terminal_settings();
-    println!("Shutting Down VM...");
+impl RunOptions {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = RunOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--debug" => options.debug = true,
+                "--os" => options.os = Some(Os::Bundled),
+                "--no-tty" => {
+                    options.input.get_or_insert(Input::Stdin);
+                }
+                "--input" => options.input = Some(Input::File(args.next()?.clone())),
+                "--input-string" => options.input = Some(Input::Text(args.next()?.clone())),
+                "--output" => {
+                    options.output = Some(args.next()?.clone());
+                    options.input.get_or_insert(Input::Stdin);
+                }
+                "--timeout" => {
+                    let seconds: f64 = args.next()?.parse().ok()?;
+                    options.limits.max_time = Some(Duration::try_from_secs_f64(seconds).ok()?);
+                }
+                "--max-instructions" => {
+                    options.limits.max_instructions = Some(args.next()?.parse().ok()?)
+                }
+                "--max-output" => options.limits.max_output = Some(args.next()?.parse().ok()?),
+                "--profile" => options.profile = true,
+                "--coverage" => options.coverage = Some(args.next()?.clone()),
+                "--lcov" => options.lcov = Some(args.next()?.clone()),
+                "--save-on-halt" => options.save_on_halt = Some(args.next()?.clone()),
+                "--resume" => options.resume = Some(args.next()?.clone()),
+                "--record" => options.record = Some(args.next()?.clone()),
+                "--replay" => options.replay = Some(args.next()?.clone()),
+                "--history" => options.history = Some(args.next()?.parse().ok()?),
+                "--start" => {
+                    options.start = Some(u16::try_from(asm::parse_number(args.next()?)?).ok()?)
+                }
+                "--trace" => options.trace = Some(args.next()?.clone()),
+                "--trace-range" => {
+                    let (start, end) = args.next()?.split_once("..")?;
+                    let start = u16::try_from(asm::parse_number(start)?).ok()?;
+                    let end = u16::try_from(asm::parse_number(end)?).ok()?;
+                    options.trace_filter.range = Some((start, end));
+                }
+                "--trace-op" => {
+                    for name in args.next()?.split(',') {
+                        options.trace_filter.opcodes.push(name.parse().ok()?);
+                    }
+                }
+                _ if arg.starts_with("--os=") => {
+                    options.os = Some(Os::Image(arg["--os=".len()..].to_string()))
+                }
+                _ if arg.starts_with("--") => return None,
+                _ => options.images.push(arg.clone()),
+            }
+        }
+        /* a snapshot holds all of memory, loading images too would only be overwritten */
+        let loads_memory =
+            !options.images.is_empty() || options.os.is_some() || options.start.is_some();
+        match options.resume {
+            Some(_) => (!loads_memory).then_some(options),
+            None => (!options.images.is_empty()).then_some(options),
+        }
+    }
+
+    fn batch(&self) -> bool {
+        self.input.is_some()
+    }
+}
+
+fn run(args: &[String]) {
+    let options = RunOptions::parse(args).unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(EXIT_USAGE);
+    });
+
+    /* with a snapshot or a recording to save, Ctrl-C stops the program instead of exiting */
+    let interrupted = if options.save_on_halt.is_some() || options.record.is_some() {
+        Some(utils::terminal::spawn_control_c_flag().unwrap())
+    } else {
+        utils::terminal::spawn_control_c_handler().unwrap();
+        None
+    };
+
+    let mut vm = match &options.input {
+        Some(input) => VM::with_console(batch_console(input, options.output.as_deref())),
+        None => VM::new(),
+    };
+
+    match &options.os {
+        Some(Os::Bundled) => {
+            let os = os::bundled();
+            vm.load_os(&os.image);
+            vm.symbols_mut().extend(&os.symbols);
+        }
+        Some(Os::Image(path)) => {
+            if let Err(e) = vm.load_image(path) {
+                eprintln!("{}", e);
+                process::exit(EXIT_FAULT);
+            }
+            vm.set_trap_mode(TrapMode::Table);
+            vm.set_exception_mode(ExceptionMode::Vector);
+        }
+        None => {}
+    }
+
+    /* the program starts at the origin of the first image unless told otherwise */
+    for (index, image) in options.images.iter().enumerate() {
+        match vm.load_image(image) {
+            Ok(origin) if index == 0 => vm.set_pc(options.start.unwrap_or(origin)),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_FAULT);
+            }
+        }
+    }
+
+    if let Some(path) = &options.resume {
+        if let Err(e) = vm.load_snapshot(path) {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAULT);
+        }
+    }
+
+    if let Some(path) = &options.replay {
+        match InputLog::read(path) {
+            Ok(log) => vm.replay_input(&log),
+            Err(e) => {
+                eprintln!("failed to load {}: {}", path, e);
+                process::exit(EXIT_FAULT);
+            }
+        }
+    }
+    if options.record.is_some() {
+        vm.record_input();
+    }
+
+    if options.debug {
+        if let Some(limit) = options.history {
+            vm.set_history_limit(limit);
+        }
+        let result = Debugger::new(&mut vm).run(io::stdin().lock(), io::stdout());
+        if let Err(e) = result {
+            eprintln!("debugger: {}", e);
+            process::exit(EXIT_FAULT);
+        }
+        if let Err(message) = save_snapshot(&vm, &options).and(write_recording(&vm, &options)) {
+            eprintln!("{}", message);
+            process::exit(EXIT_FAULT);
+        }
+        return;
+    }
+
+    let mut tracer = options.trace.as_ref().map(|path| match File::create(path) {
+        Ok(file) => Tracer::with_filter(BufWriter::new(file), options.trace_filter.clone()),
+        Err(e) => {
+            eprintln!("failed to create {}: {}", path, e);
+            process::exit(EXIT_FAULT);
+        }
+    });
+
+    let mut profiler = options.profile.then(Profiler::new);
+    let mut coverage = (options.coverage.is_some() || options.lcov.is_some()).then(Coverage::new);
+
+    vm.set_limits(options.limits);
+    let mut traced = Ok(());
+    let observed = tracer.is_some() || profiler.is_some() || coverage.is_some();
+    let result = if observed || interrupted.is_some() {
+        vm.run_until(|vm, result| {
+            if let Some(profiler) = &mut profiler {
+                profiler.record(result);
+            }
+            if let Some(coverage) = &mut coverage {
+                coverage.record(vm, result);
+            }
+            if let Some(tracer) = &mut tracer {
+                traced = tracer.record(vm, result);
+            }
+            let stop = interrupted
+                .as_ref()
+                .is_some_and(|flag| flag.load(Ordering::SeqCst));
+            traced.is_err() || stop
+        })
+    } else {
+        vm.run()
+    };
+    if let Some(tracer) = &mut tracer {
+        traced = traced.and_then(|()| tracer.flush());
+    }
+    /* a limit stops the program before an instruction, so a snapshot can carry on from it */
+    if let Err(VmError::LimitExceeded { .. }) = result {
+        vm.set_running(true);
+    }
+    let written = vm
+        .console_mut()
+        .flush()
+        .map_err(|e| format!("failed to write the output: {}", e))
+        .and(traced.map_err(|e| format!("failed to write the trace: {}", e)))
+        .and(match &coverage {
+            Some(coverage) => write_coverage(coverage, &vm, &options),
+            None => Ok(()),
+        })
+        .and(save_snapshot(&vm, &options))
+        .and(write_recording(&vm, &options));
+    let was_interrupted = interrupted.is_some_and(|flag| flag.load(Ordering::SeqCst));
+    let (status, message) = match result {
+        Ok(_) => match written {
+            Ok(()) if was_interrupted => (EXIT_INTERRUPTED, "VM interrupted".to_string()),
+            Ok(()) => (0, String::new()),
+            Err(message) => (EXIT_FAULT, message),
+        },
+        Err(e @ VmError::LimitExceeded { limit, pc }) => {
+            let status = match limit {
+                Limit::Time(_) => EXIT_TIMEOUT,
+                Limit::Instructions(_) => EXIT_INSTRUCTION_LIMIT,
+                Limit::Output(_) => EXIT_OUTPUT_LIMIT,
+            };
+            let location = vm.symbols().format_address(pc);
+            (status, format!("VM stopped: {} ({})", e, location))
+        }
+        Err(e) => match e.pc() {
+            Some(pc) => (
+                EXIT_FAULT,
+                format!("VM fault: {} ({})", e, vm.symbols().format_address(pc)),
+            ),
+            None => (EXIT_FAULT, format!("VM fault: {}", e)),
+        },
+    };
+    if let Some(profiler) = &profiler {
+        eprint!("\n{}", profiler.report(&vm, PROFILE_TOP));
+    }
+    /* dropping the VM drops its console, which gives the terminal back */
+    drop(vm);
+
+    if status != 0 {
+        eprintln!("{}", message);
+        process::exit(status);
+    }
+    if !options.batch() {
+        println!("Shutting Down VM...");
+    }
+}
+
+/* saves the machine to the --save-on-halt snapshot, if asked for */
+fn save_snapshot(vm: &VM, options: &RunOptions) -> Result<(), String> {
+    match &options.save_on_halt {
+        Some(path) => vm
+            .save_snapshot(path)
+            .map_err(|e| format!("failed to write {}: {}", path, e)),
+        None => Ok(()),
+    }
+}
+
+/* writes the input recorded for --record, if asked for */
+fn write_recording(vm: &VM, options: &RunOptions) -> Result<(), String> {
+    match (&options.record, vm.recorded_input()) {
+        (Some(path), Some(log)) => log
+            .write(path)
+            .map_err(|e| format!("failed to write {}: {}", path, e)),
+        _ => Ok(()),
+    }
+}
+
+/* writes the coverage reports asked for, over the images run */
+fn write_coverage(coverage: &Coverage, vm: &VM, options: &RunOptions) -> Result<(), String> {
+    let mut report = String::new();
+    let mut lcov = String::new();
+    for path in &options.images {
+        let image = ObjectImage::read(path).map_err(|e| e.to_string())?;
+        let map_path = Path::new(path).with_extension("map");
+        let map = if map_path.is_file() {
+            let map = SourceMap::read(&map_path.to_string_lossy())
+                .map_err(|e| format!("failed to load {}: {}", map_path.display(), e))?;
+            Some(map)
+        } else {
+            None
+        };
+        if options.images.len() > 1 {
+            report.push_str(&format!("; {}\n", path));
+        }
+        report.push_str(&coverage.report(vm, &image, map.as_ref()));
+        match &map {
+            Some(map) => lcov.push_str(&coverage.lcov(&image, map)),
+            None if options.lcov.is_some() => {
+                eprintln!("no source map for {}, assemble it with lc3 asm", path)
+            }
+            None => {}
+        }
+    }
+    for (path, contents) in [(&options.coverage, report), (&options.lcov, lcov)] {
+        if let Some(path) = path {
+            fs::write(path, contents).map_err(|e| format!("failed to write {}: {}", path, e))?;
+        }
+    }
+    Ok(())
+}
+
+/* a console fed from the batch input, writing to the output file or stdout */
+fn batch_console(input: &Input, output: Option<&str>) -> StreamConsole<Box<dyn Write>> {
+    let input = match input {
+        Input::Stdin => {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+        }
+        Input::File(path) => fs::read(path),
+        Input::Text(text) => Ok(text.clone().into_bytes()),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("failed to read the input: {}", e);
+        process::exit(EXIT_FAULT);
+    });
+    let output: Box<dyn Write> = match output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("failed to create {}: {}", path, e);
+                process::exit(EXIT_FAULT);
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    StreamConsole::new(&input, output)
 }
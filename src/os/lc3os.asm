@@ -0,0 +1,718 @@
+; The operating system bundled with lc3-vm.
+;
+; It fills in the trap vector table (x0000-x00FF) and the interrupt vector
+; table (x0100-x01FF), and provides the standard trap routines working
+; directly on the device registers. Trap routines are entered with
+; R7 <- PC; PC <- mem[trapvect8] and return with RET. Exception handlers,
+; and interrupts the program has no handler for, are entered in supervisor
+; mode, report the exception or interrupt and halt.
+
+        .ORIG x0000
+
+; Trap vector table
+        .FILL BAD_TRAP          ; x00
+        .FILL BAD_TRAP          ; x01
+        .FILL BAD_TRAP          ; x02
+        .FILL BAD_TRAP          ; x03
+        .FILL BAD_TRAP          ; x04
+        .FILL BAD_TRAP          ; x05
+        .FILL BAD_TRAP          ; x06
+        .FILL BAD_TRAP          ; x07
+        .FILL BAD_TRAP          ; x08
+        .FILL BAD_TRAP          ; x09
+        .FILL BAD_TRAP          ; x0A
+        .FILL BAD_TRAP          ; x0B
+        .FILL BAD_TRAP          ; x0C
+        .FILL BAD_TRAP          ; x0D
+        .FILL BAD_TRAP          ; x0E
+        .FILL BAD_TRAP          ; x0F
+        .FILL BAD_TRAP          ; x10
+        .FILL BAD_TRAP          ; x11
+        .FILL BAD_TRAP          ; x12
+        .FILL BAD_TRAP          ; x13
+        .FILL BAD_TRAP          ; x14
+        .FILL BAD_TRAP          ; x15
+        .FILL BAD_TRAP          ; x16
+        .FILL BAD_TRAP          ; x17
+        .FILL BAD_TRAP          ; x18
+        .FILL BAD_TRAP          ; x19
+        .FILL BAD_TRAP          ; x1A
+        .FILL BAD_TRAP          ; x1B
+        .FILL BAD_TRAP          ; x1C
+        .FILL BAD_TRAP          ; x1D
+        .FILL BAD_TRAP          ; x1E
+        .FILL BAD_TRAP          ; x1F
+        .FILL TRAP_GETC       ; x20
+        .FILL TRAP_OUT        ; x21
+        .FILL TRAP_PUTS       ; x22
+        .FILL TRAP_IN         ; x23
+        .FILL TRAP_PUTSP      ; x24
+        .FILL TRAP_HALT       ; x25
+        .FILL BAD_TRAP          ; x26
+        .FILL BAD_TRAP          ; x27
+        .FILL BAD_TRAP          ; x28
+        .FILL BAD_TRAP          ; x29
+        .FILL BAD_TRAP          ; x2A
+        .FILL BAD_TRAP          ; x2B
+        .FILL BAD_TRAP          ; x2C
+        .FILL BAD_TRAP          ; x2D
+        .FILL BAD_TRAP          ; x2E
+        .FILL BAD_TRAP          ; x2F
+        .FILL BAD_TRAP          ; x30
+        .FILL BAD_TRAP          ; x31
+        .FILL BAD_TRAP          ; x32
+        .FILL BAD_TRAP          ; x33
+        .FILL BAD_TRAP          ; x34
+        .FILL BAD_TRAP          ; x35
+        .FILL BAD_TRAP          ; x36
+        .FILL BAD_TRAP          ; x37
+        .FILL BAD_TRAP          ; x38
+        .FILL BAD_TRAP          ; x39
+        .FILL BAD_TRAP          ; x3A
+        .FILL BAD_TRAP          ; x3B
+        .FILL BAD_TRAP          ; x3C
+        .FILL BAD_TRAP          ; x3D
+        .FILL BAD_TRAP          ; x3E
+        .FILL BAD_TRAP          ; x3F
+        .FILL BAD_TRAP          ; x40
+        .FILL BAD_TRAP          ; x41
+        .FILL BAD_TRAP          ; x42
+        .FILL BAD_TRAP          ; x43
+        .FILL BAD_TRAP          ; x44
+        .FILL BAD_TRAP          ; x45
+        .FILL BAD_TRAP          ; x46
+        .FILL BAD_TRAP          ; x47
+        .FILL BAD_TRAP          ; x48
+        .FILL BAD_TRAP          ; x49
+        .FILL BAD_TRAP          ; x4A
+        .FILL BAD_TRAP          ; x4B
+        .FILL BAD_TRAP          ; x4C
+        .FILL BAD_TRAP          ; x4D
+        .FILL BAD_TRAP          ; x4E
+        .FILL BAD_TRAP          ; x4F
+        .FILL BAD_TRAP          ; x50
+        .FILL BAD_TRAP          ; x51
+        .FILL BAD_TRAP          ; x52
+        .FILL BAD_TRAP          ; x53
+        .FILL BAD_TRAP          ; x54
+        .FILL BAD_TRAP          ; x55
+        .FILL BAD_TRAP          ; x56
+        .FILL BAD_TRAP          ; x57
+        .FILL BAD_TRAP          ; x58
+        .FILL BAD_TRAP          ; x59
+        .FILL BAD_TRAP          ; x5A
+        .FILL BAD_TRAP          ; x5B
+        .FILL BAD_TRAP          ; x5C
+        .FILL BAD_TRAP          ; x5D
+        .FILL BAD_TRAP          ; x5E
+        .FILL BAD_TRAP          ; x5F
+        .FILL BAD_TRAP          ; x60
+        .FILL BAD_TRAP          ; x61
+        .FILL BAD_TRAP          ; x62
+        .FILL BAD_TRAP          ; x63
+        .FILL BAD_TRAP          ; x64
+        .FILL BAD_TRAP          ; x65
+        .FILL BAD_TRAP          ; x66
+        .FILL BAD_TRAP          ; x67
+        .FILL BAD_TRAP          ; x68
+        .FILL BAD_TRAP          ; x69
+        .FILL BAD_TRAP          ; x6A
+        .FILL BAD_TRAP          ; x6B
+        .FILL BAD_TRAP          ; x6C
+        .FILL BAD_TRAP          ; x6D
+        .FILL BAD_TRAP          ; x6E
+        .FILL BAD_TRAP          ; x6F
+        .FILL BAD_TRAP          ; x70
+        .FILL BAD_TRAP          ; x71
+        .FILL BAD_TRAP          ; x72
+        .FILL BAD_TRAP          ; x73
+        .FILL BAD_TRAP          ; x74
+        .FILL BAD_TRAP          ; x75
+        .FILL BAD_TRAP          ; x76
+        .FILL BAD_TRAP          ; x77
+        .FILL BAD_TRAP          ; x78
+        .FILL BAD_TRAP          ; x79
+        .FILL BAD_TRAP          ; x7A
+        .FILL BAD_TRAP          ; x7B
+        .FILL BAD_TRAP          ; x7C
+        .FILL BAD_TRAP          ; x7D
+        .FILL BAD_TRAP          ; x7E
+        .FILL BAD_TRAP          ; x7F
+        .FILL BAD_TRAP          ; x80
+        .FILL BAD_TRAP          ; x81
+        .FILL BAD_TRAP          ; x82
+        .FILL BAD_TRAP          ; x83
+        .FILL BAD_TRAP          ; x84
+        .FILL BAD_TRAP          ; x85
+        .FILL BAD_TRAP          ; x86
+        .FILL BAD_TRAP          ; x87
+        .FILL BAD_TRAP          ; x88
+        .FILL BAD_TRAP          ; x89
+        .FILL BAD_TRAP          ; x8A
+        .FILL BAD_TRAP          ; x8B
+        .FILL BAD_TRAP          ; x8C
+        .FILL BAD_TRAP          ; x8D
+        .FILL BAD_TRAP          ; x8E
+        .FILL BAD_TRAP          ; x8F
+        .FILL BAD_TRAP          ; x90
+        .FILL BAD_TRAP          ; x91
+        .FILL BAD_TRAP          ; x92
+        .FILL BAD_TRAP          ; x93
+        .FILL BAD_TRAP          ; x94
+        .FILL BAD_TRAP          ; x95
+        .FILL BAD_TRAP          ; x96
+        .FILL BAD_TRAP          ; x97
+        .FILL BAD_TRAP          ; x98
+        .FILL BAD_TRAP          ; x99
+        .FILL BAD_TRAP          ; x9A
+        .FILL BAD_TRAP          ; x9B
+        .FILL BAD_TRAP          ; x9C
+        .FILL BAD_TRAP          ; x9D
+        .FILL BAD_TRAP          ; x9E
+        .FILL BAD_TRAP          ; x9F
+        .FILL BAD_TRAP          ; xA0
+        .FILL BAD_TRAP          ; xA1
+        .FILL BAD_TRAP          ; xA2
+        .FILL BAD_TRAP          ; xA3
+        .FILL BAD_TRAP          ; xA4
+        .FILL BAD_TRAP          ; xA5
+        .FILL BAD_TRAP          ; xA6
+        .FILL BAD_TRAP          ; xA7
+        .FILL BAD_TRAP          ; xA8
+        .FILL BAD_TRAP          ; xA9
+        .FILL BAD_TRAP          ; xAA
+        .FILL BAD_TRAP          ; xAB
+        .FILL BAD_TRAP          ; xAC
+        .FILL BAD_TRAP          ; xAD
+        .FILL BAD_TRAP          ; xAE
+        .FILL BAD_TRAP          ; xAF
+        .FILL BAD_TRAP          ; xB0
+        .FILL BAD_TRAP          ; xB1
+        .FILL BAD_TRAP          ; xB2
+        .FILL BAD_TRAP          ; xB3
+        .FILL BAD_TRAP          ; xB4
+        .FILL BAD_TRAP          ; xB5
+        .FILL BAD_TRAP          ; xB6
+        .FILL BAD_TRAP          ; xB7
+        .FILL BAD_TRAP          ; xB8
+        .FILL BAD_TRAP          ; xB9
+        .FILL BAD_TRAP          ; xBA
+        .FILL BAD_TRAP          ; xBB
+        .FILL BAD_TRAP          ; xBC
+        .FILL BAD_TRAP          ; xBD
+        .FILL BAD_TRAP          ; xBE
+        .FILL BAD_TRAP          ; xBF
+        .FILL BAD_TRAP          ; xC0
+        .FILL BAD_TRAP          ; xC1
+        .FILL BAD_TRAP          ; xC2
+        .FILL BAD_TRAP          ; xC3
+        .FILL BAD_TRAP          ; xC4
+        .FILL BAD_TRAP          ; xC5
+        .FILL BAD_TRAP          ; xC6
+        .FILL BAD_TRAP          ; xC7
+        .FILL BAD_TRAP          ; xC8
+        .FILL BAD_TRAP          ; xC9
+        .FILL BAD_TRAP          ; xCA
+        .FILL BAD_TRAP          ; xCB
+        .FILL BAD_TRAP          ; xCC
+        .FILL BAD_TRAP          ; xCD
+        .FILL BAD_TRAP          ; xCE
+        .FILL BAD_TRAP          ; xCF
+        .FILL BAD_TRAP          ; xD0
+        .FILL BAD_TRAP          ; xD1
+        .FILL BAD_TRAP          ; xD2
+        .FILL BAD_TRAP          ; xD3
+        .FILL BAD_TRAP          ; xD4
+        .FILL BAD_TRAP          ; xD5
+        .FILL BAD_TRAP          ; xD6
+        .FILL BAD_TRAP          ; xD7
+        .FILL BAD_TRAP          ; xD8
+        .FILL BAD_TRAP          ; xD9
+        .FILL BAD_TRAP          ; xDA
+        .FILL BAD_TRAP          ; xDB
+        .FILL BAD_TRAP          ; xDC
+        .FILL BAD_TRAP          ; xDD
+        .FILL BAD_TRAP          ; xDE
+        .FILL BAD_TRAP          ; xDF
+        .FILL BAD_TRAP          ; xE0
+        .FILL BAD_TRAP          ; xE1
+        .FILL BAD_TRAP          ; xE2
+        .FILL BAD_TRAP          ; xE3
+        .FILL BAD_TRAP          ; xE4
+        .FILL BAD_TRAP          ; xE5
+        .FILL BAD_TRAP          ; xE6
+        .FILL BAD_TRAP          ; xE7
+        .FILL BAD_TRAP          ; xE8
+        .FILL BAD_TRAP          ; xE9
+        .FILL BAD_TRAP          ; xEA
+        .FILL BAD_TRAP          ; xEB
+        .FILL BAD_TRAP          ; xEC
+        .FILL BAD_TRAP          ; xED
+        .FILL BAD_TRAP          ; xEE
+        .FILL BAD_TRAP          ; xEF
+        .FILL BAD_TRAP          ; xF0
+        .FILL BAD_TRAP          ; xF1
+        .FILL BAD_TRAP          ; xF2
+        .FILL BAD_TRAP          ; xF3
+        .FILL BAD_TRAP          ; xF4
+        .FILL BAD_TRAP          ; xF5
+        .FILL BAD_TRAP          ; xF6
+        .FILL BAD_TRAP          ; xF7
+        .FILL BAD_TRAP          ; xF8
+        .FILL BAD_TRAP          ; xF9
+        .FILL BAD_TRAP          ; xFA
+        .FILL BAD_TRAP          ; xFB
+        .FILL BAD_TRAP          ; xFC
+        .FILL BAD_TRAP          ; xFD
+        .FILL BAD_TRAP          ; xFE
+        .FILL BAD_TRAP          ; xFF
+
+; Interrupt vector table
+        .FILL EX_PRIVILEGE    ; x100
+        .FILL EX_ILLEGAL      ; x101
+        .FILL EX_ACCESS       ; x102
+        .FILL BAD_INTERRUPT     ; x103
+        .FILL BAD_INTERRUPT     ; x104
+        .FILL BAD_INTERRUPT     ; x105
+        .FILL BAD_INTERRUPT     ; x106
+        .FILL BAD_INTERRUPT     ; x107
+        .FILL BAD_INTERRUPT     ; x108
+        .FILL BAD_INTERRUPT     ; x109
+        .FILL BAD_INTERRUPT     ; x10A
+        .FILL BAD_INTERRUPT     ; x10B
+        .FILL BAD_INTERRUPT     ; x10C
+        .FILL BAD_INTERRUPT     ; x10D
+        .FILL BAD_INTERRUPT     ; x10E
+        .FILL BAD_INTERRUPT     ; x10F
+        .FILL BAD_INTERRUPT     ; x110
+        .FILL BAD_INTERRUPT     ; x111
+        .FILL BAD_INTERRUPT     ; x112
+        .FILL BAD_INTERRUPT     ; x113
+        .FILL BAD_INTERRUPT     ; x114
+        .FILL BAD_INTERRUPT     ; x115
+        .FILL BAD_INTERRUPT     ; x116
+        .FILL BAD_INTERRUPT     ; x117
+        .FILL BAD_INTERRUPT     ; x118
+        .FILL BAD_INTERRUPT     ; x119
+        .FILL BAD_INTERRUPT     ; x11A
+        .FILL BAD_INTERRUPT     ; x11B
+        .FILL BAD_INTERRUPT     ; x11C
+        .FILL BAD_INTERRUPT     ; x11D
+        .FILL BAD_INTERRUPT     ; x11E
+        .FILL BAD_INTERRUPT     ; x11F
+        .FILL BAD_INTERRUPT     ; x120
+        .FILL BAD_INTERRUPT     ; x121
+        .FILL BAD_INTERRUPT     ; x122
+        .FILL BAD_INTERRUPT     ; x123
+        .FILL BAD_INTERRUPT     ; x124
+        .FILL BAD_INTERRUPT     ; x125
+        .FILL BAD_INTERRUPT     ; x126
+        .FILL BAD_INTERRUPT     ; x127
+        .FILL BAD_INTERRUPT     ; x128
+        .FILL BAD_INTERRUPT     ; x129
+        .FILL BAD_INTERRUPT     ; x12A
+        .FILL BAD_INTERRUPT     ; x12B
+        .FILL BAD_INTERRUPT     ; x12C
+        .FILL BAD_INTERRUPT     ; x12D
+        .FILL BAD_INTERRUPT     ; x12E
+        .FILL BAD_INTERRUPT     ; x12F
+        .FILL BAD_INTERRUPT     ; x130
+        .FILL BAD_INTERRUPT     ; x131
+        .FILL BAD_INTERRUPT     ; x132
+        .FILL BAD_INTERRUPT     ; x133
+        .FILL BAD_INTERRUPT     ; x134
+        .FILL BAD_INTERRUPT     ; x135
+        .FILL BAD_INTERRUPT     ; x136
+        .FILL BAD_INTERRUPT     ; x137
+        .FILL BAD_INTERRUPT     ; x138
+        .FILL BAD_INTERRUPT     ; x139
+        .FILL BAD_INTERRUPT     ; x13A
+        .FILL BAD_INTERRUPT     ; x13B
+        .FILL BAD_INTERRUPT     ; x13C
+        .FILL BAD_INTERRUPT     ; x13D
+        .FILL BAD_INTERRUPT     ; x13E
+        .FILL BAD_INTERRUPT     ; x13F
+        .FILL BAD_INTERRUPT     ; x140
+        .FILL BAD_INTERRUPT     ; x141
+        .FILL BAD_INTERRUPT     ; x142
+        .FILL BAD_INTERRUPT     ; x143
+        .FILL BAD_INTERRUPT     ; x144
+        .FILL BAD_INTERRUPT     ; x145
+        .FILL BAD_INTERRUPT     ; x146
+        .FILL BAD_INTERRUPT     ; x147
+        .FILL BAD_INTERRUPT     ; x148
+        .FILL BAD_INTERRUPT     ; x149
+        .FILL BAD_INTERRUPT     ; x14A
+        .FILL BAD_INTERRUPT     ; x14B
+        .FILL BAD_INTERRUPT     ; x14C
+        .FILL BAD_INTERRUPT     ; x14D
+        .FILL BAD_INTERRUPT     ; x14E
+        .FILL BAD_INTERRUPT     ; x14F
+        .FILL BAD_INTERRUPT     ; x150
+        .FILL BAD_INTERRUPT     ; x151
+        .FILL BAD_INTERRUPT     ; x152
+        .FILL BAD_INTERRUPT     ; x153
+        .FILL BAD_INTERRUPT     ; x154
+        .FILL BAD_INTERRUPT     ; x155
+        .FILL BAD_INTERRUPT     ; x156
+        .FILL BAD_INTERRUPT     ; x157
+        .FILL BAD_INTERRUPT     ; x158
+        .FILL BAD_INTERRUPT     ; x159
+        .FILL BAD_INTERRUPT     ; x15A
+        .FILL BAD_INTERRUPT     ; x15B
+        .FILL BAD_INTERRUPT     ; x15C
+        .FILL BAD_INTERRUPT     ; x15D
+        .FILL BAD_INTERRUPT     ; x15E
+        .FILL BAD_INTERRUPT     ; x15F
+        .FILL BAD_INTERRUPT     ; x160
+        .FILL BAD_INTERRUPT     ; x161
+        .FILL BAD_INTERRUPT     ; x162
+        .FILL BAD_INTERRUPT     ; x163
+        .FILL BAD_INTERRUPT     ; x164
+        .FILL BAD_INTERRUPT     ; x165
+        .FILL BAD_INTERRUPT     ; x166
+        .FILL BAD_INTERRUPT     ; x167
+        .FILL BAD_INTERRUPT     ; x168
+        .FILL BAD_INTERRUPT     ; x169
+        .FILL BAD_INTERRUPT     ; x16A
+        .FILL BAD_INTERRUPT     ; x16B
+        .FILL BAD_INTERRUPT     ; x16C
+        .FILL BAD_INTERRUPT     ; x16D
+        .FILL BAD_INTERRUPT     ; x16E
+        .FILL BAD_INTERRUPT     ; x16F
+        .FILL BAD_INTERRUPT     ; x170
+        .FILL BAD_INTERRUPT     ; x171
+        .FILL BAD_INTERRUPT     ; x172
+        .FILL BAD_INTERRUPT     ; x173
+        .FILL BAD_INTERRUPT     ; x174
+        .FILL BAD_INTERRUPT     ; x175
+        .FILL BAD_INTERRUPT     ; x176
+        .FILL BAD_INTERRUPT     ; x177
+        .FILL BAD_INTERRUPT     ; x178
+        .FILL BAD_INTERRUPT     ; x179
+        .FILL BAD_INTERRUPT     ; x17A
+        .FILL BAD_INTERRUPT     ; x17B
+        .FILL BAD_INTERRUPT     ; x17C
+        .FILL BAD_INTERRUPT     ; x17D
+        .FILL BAD_INTERRUPT     ; x17E
+        .FILL BAD_INTERRUPT     ; x17F
+        .FILL BAD_INTERRUPT     ; x180
+        .FILL BAD_INTERRUPT     ; x181
+        .FILL BAD_INTERRUPT     ; x182
+        .FILL BAD_INTERRUPT     ; x183
+        .FILL BAD_INTERRUPT     ; x184
+        .FILL BAD_INTERRUPT     ; x185
+        .FILL BAD_INTERRUPT     ; x186
+        .FILL BAD_INTERRUPT     ; x187
+        .FILL BAD_INTERRUPT     ; x188
+        .FILL BAD_INTERRUPT     ; x189
+        .FILL BAD_INTERRUPT     ; x18A
+        .FILL BAD_INTERRUPT     ; x18B
+        .FILL BAD_INTERRUPT     ; x18C
+        .FILL BAD_INTERRUPT     ; x18D
+        .FILL BAD_INTERRUPT     ; x18E
+        .FILL BAD_INTERRUPT     ; x18F
+        .FILL BAD_INTERRUPT     ; x190
+        .FILL BAD_INTERRUPT     ; x191
+        .FILL BAD_INTERRUPT     ; x192
+        .FILL BAD_INTERRUPT     ; x193
+        .FILL BAD_INTERRUPT     ; x194
+        .FILL BAD_INTERRUPT     ; x195
+        .FILL BAD_INTERRUPT     ; x196
+        .FILL BAD_INTERRUPT     ; x197
+        .FILL BAD_INTERRUPT     ; x198
+        .FILL BAD_INTERRUPT     ; x199
+        .FILL BAD_INTERRUPT     ; x19A
+        .FILL BAD_INTERRUPT     ; x19B
+        .FILL BAD_INTERRUPT     ; x19C
+        .FILL BAD_INTERRUPT     ; x19D
+        .FILL BAD_INTERRUPT     ; x19E
+        .FILL BAD_INTERRUPT     ; x19F
+        .FILL BAD_INTERRUPT     ; x1A0
+        .FILL BAD_INTERRUPT     ; x1A1
+        .FILL BAD_INTERRUPT     ; x1A2
+        .FILL BAD_INTERRUPT     ; x1A3
+        .FILL BAD_INTERRUPT     ; x1A4
+        .FILL BAD_INTERRUPT     ; x1A5
+        .FILL BAD_INTERRUPT     ; x1A6
+        .FILL BAD_INTERRUPT     ; x1A7
+        .FILL BAD_INTERRUPT     ; x1A8
+        .FILL BAD_INTERRUPT     ; x1A9
+        .FILL BAD_INTERRUPT     ; x1AA
+        .FILL BAD_INTERRUPT     ; x1AB
+        .FILL BAD_INTERRUPT     ; x1AC
+        .FILL BAD_INTERRUPT     ; x1AD
+        .FILL BAD_INTERRUPT     ; x1AE
+        .FILL BAD_INTERRUPT     ; x1AF
+        .FILL BAD_INTERRUPT     ; x1B0
+        .FILL BAD_INTERRUPT     ; x1B1
+        .FILL BAD_INTERRUPT     ; x1B2
+        .FILL BAD_INTERRUPT     ; x1B3
+        .FILL BAD_INTERRUPT     ; x1B4
+        .FILL BAD_INTERRUPT     ; x1B5
+        .FILL BAD_INTERRUPT     ; x1B6
+        .FILL BAD_INTERRUPT     ; x1B7
+        .FILL BAD_INTERRUPT     ; x1B8
+        .FILL BAD_INTERRUPT     ; x1B9
+        .FILL BAD_INTERRUPT     ; x1BA
+        .FILL BAD_INTERRUPT     ; x1BB
+        .FILL BAD_INTERRUPT     ; x1BC
+        .FILL BAD_INTERRUPT     ; x1BD
+        .FILL BAD_INTERRUPT     ; x1BE
+        .FILL BAD_INTERRUPT     ; x1BF
+        .FILL BAD_INTERRUPT     ; x1C0
+        .FILL BAD_INTERRUPT     ; x1C1
+        .FILL BAD_INTERRUPT     ; x1C2
+        .FILL BAD_INTERRUPT     ; x1C3
+        .FILL BAD_INTERRUPT     ; x1C4
+        .FILL BAD_INTERRUPT     ; x1C5
+        .FILL BAD_INTERRUPT     ; x1C6
+        .FILL BAD_INTERRUPT     ; x1C7
+        .FILL BAD_INTERRUPT     ; x1C8
+        .FILL BAD_INTERRUPT     ; x1C9
+        .FILL BAD_INTERRUPT     ; x1CA
+        .FILL BAD_INTERRUPT     ; x1CB
+        .FILL BAD_INTERRUPT     ; x1CC
+        .FILL BAD_INTERRUPT     ; x1CD
+        .FILL BAD_INTERRUPT     ; x1CE
+        .FILL BAD_INTERRUPT     ; x1CF
+        .FILL BAD_INTERRUPT     ; x1D0
+        .FILL BAD_INTERRUPT     ; x1D1
+        .FILL BAD_INTERRUPT     ; x1D2
+        .FILL BAD_INTERRUPT     ; x1D3
+        .FILL BAD_INTERRUPT     ; x1D4
+        .FILL BAD_INTERRUPT     ; x1D5
+        .FILL BAD_INTERRUPT     ; x1D6
+        .FILL BAD_INTERRUPT     ; x1D7
+        .FILL BAD_INTERRUPT     ; x1D8
+        .FILL BAD_INTERRUPT     ; x1D9
+        .FILL BAD_INTERRUPT     ; x1DA
+        .FILL BAD_INTERRUPT     ; x1DB
+        .FILL BAD_INTERRUPT     ; x1DC
+        .FILL BAD_INTERRUPT     ; x1DD
+        .FILL BAD_INTERRUPT     ; x1DE
+        .FILL BAD_INTERRUPT     ; x1DF
+        .FILL BAD_INTERRUPT     ; x1E0
+        .FILL BAD_INTERRUPT     ; x1E1
+        .FILL BAD_INTERRUPT     ; x1E2
+        .FILL BAD_INTERRUPT     ; x1E3
+        .FILL BAD_INTERRUPT     ; x1E4
+        .FILL BAD_INTERRUPT     ; x1E5
+        .FILL BAD_INTERRUPT     ; x1E6
+        .FILL BAD_INTERRUPT     ; x1E7
+        .FILL BAD_INTERRUPT     ; x1E8
+        .FILL BAD_INTERRUPT     ; x1E9
+        .FILL BAD_INTERRUPT     ; x1EA
+        .FILL BAD_INTERRUPT     ; x1EB
+        .FILL BAD_INTERRUPT     ; x1EC
+        .FILL BAD_INTERRUPT     ; x1ED
+        .FILL BAD_INTERRUPT     ; x1EE
+        .FILL BAD_INTERRUPT     ; x1EF
+        .FILL BAD_INTERRUPT     ; x1F0
+        .FILL BAD_INTERRUPT     ; x1F1
+        .FILL BAD_INTERRUPT     ; x1F2
+        .FILL BAD_INTERRUPT     ; x1F3
+        .FILL BAD_INTERRUPT     ; x1F4
+        .FILL BAD_INTERRUPT     ; x1F5
+        .FILL BAD_INTERRUPT     ; x1F6
+        .FILL BAD_INTERRUPT     ; x1F7
+        .FILL BAD_INTERRUPT     ; x1F8
+        .FILL BAD_INTERRUPT     ; x1F9
+        .FILL BAD_INTERRUPT     ; x1FA
+        .FILL BAD_INTERRUPT     ; x1FB
+        .FILL BAD_INTERRUPT     ; x1FC
+        .FILL BAD_INTERRUPT     ; x1FD
+        .FILL BAD_INTERRUPT     ; x1FE
+        .FILL BAD_INTERRUPT     ; x1FF
+
+; Device registers and constants
+
+OS_KBSR .FILL xFE00
+OS_KBDR .FILL xFE02
+OS_DSR  .FILL xFE04
+OS_DDR  .FILL xFE06
+OS_MCR  .FILL xFFFE
+LOW_8   .FILL x00FF
+CLOCK_OFF .FILL x7FFF
+
+SAVE_R0 .FILL 0
+SAVE_R1 .FILL 0
+SAVE_R2 .FILL 0
+SAVE_R3 .FILL 0
+SAVE_R4 .FILL 0
+
+; GETC: read a character into R0, without echoing it
+
+TRAP_GETC
+        LDI R0, OS_KBSR
+        BRzp TRAP_GETC
+        LDI R0, OS_KBDR
+        RET
+
+; OUT: write the character in R0
+
+TRAP_OUT
+        ST R1, SAVE_R1
+OUT_WAIT
+        LDI R1, OS_DSR
+        BRzp OUT_WAIT
+        STI R0, OS_DDR
+        LD R1, SAVE_R1
+        RET
+
+; PUTS: write the string of one character per word starting at R0
+
+TRAP_PUTS
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ST R2, SAVE_R2
+PUTS_LOOP
+        LDR R1, R0, #0
+        BRz PUTS_DONE
+PUTS_WAIT
+        LDI R2, OS_DSR
+        BRzp PUTS_WAIT
+        STI R1, OS_DDR
+        ADD R0, R0, #1
+        BRnzp PUTS_LOOP
+PUTS_DONE
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R2, SAVE_R2
+        RET
+
+; IN: prompt for a character and read it into R0
+
+TRAP_IN
+        ST R1, SAVE_R1
+        ST R2, SAVE_R2
+        ST R3, SAVE_R3
+        LEA R1, IN_PROMPT
+IN_LOOP
+        LDR R2, R1, #0
+        BRz IN_READ
+IN_WAIT
+        LDI R3, OS_DSR
+        BRzp IN_WAIT
+        STI R2, OS_DDR
+        ADD R1, R1, #1
+        BRnzp IN_LOOP
+IN_READ
+        LDI R3, OS_KBSR
+        BRzp IN_READ
+        LDI R0, OS_KBDR
+        LD R1, SAVE_R1
+        LD R2, SAVE_R2
+        LD R3, SAVE_R3
+        RET
+
+IN_PROMPT .STRINGZ "Enter a character: "
+
+; PUTSP: write the string of two characters per word (low byte first)
+; starting at R0
+
+TRAP_PUTSP
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ST R2, SAVE_R2
+        ST R3, SAVE_R3
+        ST R4, SAVE_R4
+PUTSP_LOOP
+        LDR R1, R0, #0
+        LD R3, LOW_8
+        AND R2, R1, R3
+        BRz PUTSP_DONE
+PUTSP_WAIT_LOW
+        LDI R4, OS_DSR
+        BRzp PUTSP_WAIT_LOW
+        STI R2, OS_DDR
+        ; rotate the word left 8 times to bring the high byte down
+        AND R3, R3, #0
+        ADD R3, R3, #8
+PUTSP_ROTATE
+        ADD R1, R1, #0
+        BRn PUTSP_CARRY
+        ADD R1, R1, R1
+        BRnzp PUTSP_NEXT
+PUTSP_CARRY
+        ADD R1, R1, R1
+        ADD R1, R1, #1
+PUTSP_NEXT
+        ADD R3, R3, #-1
+        BRp PUTSP_ROTATE
+        LD R3, LOW_8
+        AND R2, R1, R3
+        BRz PUTSP_DONE
+PUTSP_WAIT_HIGH
+        LDI R4, OS_DSR
+        BRzp PUTSP_WAIT_HIGH
+        STI R2, OS_DDR
+        ADD R0, R0, #1
+        BRnzp PUTSP_LOOP
+PUTSP_DONE
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R2, SAVE_R2
+        LD R3, SAVE_R3
+        LD R4, SAVE_R4
+        RET
+
+; HALT: stop the machine by clearing the clock enable bit of the MCR
+
+TRAP_HALT
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ST R2, SAVE_R2
+        ST R3, SAVE_R3
+        LEA R1, HALT_MESSAGE
+HALT_LOOP
+        LDR R2, R1, #0
+        BRz HALT_CLOCK
+HALT_WAIT
+        LDI R3, OS_DSR
+        BRzp HALT_WAIT
+        STI R2, OS_DDR
+        ADD R1, R1, #1
+        BRnzp HALT_LOOP
+HALT_CLOCK
+        LDI R0, OS_MCR
+        LD R1, CLOCK_OFF
+        AND R0, R0, R1
+        STI R0, OS_MCR
+        ; only reached if the clock is started again
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R2, SAVE_R2
+        LD R3, SAVE_R3
+        RET
+
+HALT_MESSAGE .STRINGZ "Halting the VM...\n"
+
+; Exception handlers and unexpected traps and interrupts
+
+EX_PRIVILEGE
+        LEA R0, PRIVILEGE_MESSAGE
+        BRnzp REPORT
+EX_ILLEGAL
+        LEA R0, ILLEGAL_MESSAGE
+        BRnzp REPORT
+EX_ACCESS
+        LEA R0, ACCESS_MESSAGE
+        BRnzp REPORT
+BAD_TRAP
+        LEA R0, BAD_TRAP_MESSAGE
+        BRnzp REPORT
+; an interrupt without a handler would be requested again as soon as it returned
+BAD_INTERRUPT
+        LEA R0, BAD_INTERRUPT_MESSAGE
+REPORT
+        PUTS
+        HALT
+
+PRIVILEGE_MESSAGE     .STRINGZ "\nPrivilege mode violation\n"
+ILLEGAL_MESSAGE       .STRINGZ "\nIllegal opcode\n"
+ACCESS_MESSAGE        .STRINGZ "\nAccess control violation\n"
+BAD_TRAP_MESSAGE      .STRINGZ "\nUnknown trap\n"
+BAD_INTERRUPT_MESSAGE .STRINGZ "\nUnexpected interrupt\n"
+
+        .END
@@ -0,0 +1,143 @@
+// Module for the LC-3 operating system bundled with the VM
+
+use crate::asm::{assemble, Assembly};
+
+const SOURCE: &str = include_str!("lc3os.asm");
+
+/// The assembly source of the bundled OS, a starting point for writing your own.
+pub fn source() -> &'static str {
+    SOURCE
+}
+
+/// The bundled OS, assembled: trap routines, exception handlers and both vector tables.
+pub fn bundled() -> Assembly {
+    assemble(SOURCE).expect("the bundled OS assembles")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{MemoryConsole, Register, TrapMode, VM};
+
+    #[test]
+    fn test_bundled_os_fills_the_vector_tables() {
+        let os = bundled();
+
+        assert_eq!(os.image.origin, 0x0000);
+        let getc = os.symbols.address_of("TRAP_GETC").unwrap();
+        let bad_trap = os.symbols.address_of("BAD_TRAP").unwrap();
+        assert_eq!(os.image.words[0x20], getc);
+        assert_eq!(os.image.words[0x00], bad_trap);
+        assert_eq!(os.image.words[0xFF], bad_trap);
+        assert_eq!(
+            os.image.words[0x101],
+            os.symbols.address_of("EX_ILLEGAL").unwrap()
+        );
+        assert!(os.image.end() < 0x3000);
+    }
+
+    #[test]
+    fn test_getc_runs_in_the_os() {
+        let mut vm = VM::with_console(MemoryConsole::with_input(b"k"));
+        vm.load_os(&bundled().image);
+        let program = assemble(".ORIG x3000\nGETC\nHALT\n.END").unwrap();
+        vm.load_object(&program.image);
+
+        vm.run_until(|vm, _| vm.pc() == 0x3001).unwrap();
+
+        assert_eq!(vm.trap_mode(), TrapMode::Table);
+        assert_eq!(vm.reg(Register::R0), 'k' as u16);
+        assert_eq!(vm.reg(Register::R7), 0x3001);
+    }
+
+    fn run_with_os(source: &str, input: &[u8]) -> (VM, MemoryConsole) {
+        let console = MemoryConsole::with_input(input);
+        let mut vm = VM::with_console(console.clone());
+        vm.load_os(&bundled().image);
+        vm.load_object(&assemble(source).unwrap().image);
+        vm.run_for(100_000).unwrap();
+        (vm, console)
+    }
+
+    #[test]
+    fn test_output_and_halt_run_in_the_os() {
+        let (vm, console) = run_with_os(
+            r#"
+            .ORIG x3000
+            LEA R0, HELLO
+            PUTS
+            LEA R0, PACKED
+            PUTSP
+            IN
+            OUT
+            HALT
+    HELLO   .STRINGZ "Hello, "
+    PACKED  .FILL x6F77     ; "wo"
+            .FILL x6C72     ; "rl"
+            .FILL x0064     ; "d"
+            .END
+            "#,
+            b"!",
+        );
+
+        assert!(!vm.is_running());
+        assert_eq!(
+            console.output_string(),
+            "Hello, worldEnter a character: !Halting the VM...\n"
+        );
+    }
+
+    #[test]
+    fn test_halt_keeps_the_registers_when_restarted() {
+        let (mut vm, _) = run_with_os(
+            "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #1
+            ADD R1, R0, #1
+            ADD R2, R0, #2
+            ADD R3, R0, #3
+            HALT
+            .END",
+            b"",
+        );
+        assert!(!vm.is_running());
+
+        vm.set_running(true);
+        vm.run_until(|vm, _| vm.pc() == 0x3006).unwrap();
+        let registers = [Register::R0, Register::R1, Register::R2, Register::R3];
+        assert_eq!(registers.map(|r| vm.reg(r)), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_interrupt_without_a_handler_halts() {
+        let (vm, console) = run_with_os(
+            "
+            .ORIG x3000
+            LD R0, IE
+            STI R0, KBSR
+LOOP        BRnzp LOOP
+IE          .FILL x4000
+KBSR        .FILL xFE00
+            .END",
+            b"k",
+        );
+
+        assert!(!vm.is_running());
+        assert_eq!(
+            console.output_string(),
+            "\nUnexpected interrupt\nHalting the VM...\n"
+        );
+    }
+
+    #[test]
+    fn test_exceptions_are_reported_by_the_os() {
+        let (vm, console) = run_with_os(".ORIG x3000\n.FILL xD000\n.END", b"");
+
+        assert!(!vm.is_running());
+        assert_eq!(
+            console.output_string(),
+            "\nIllegal opcode\nHalting the VM...\n"
+        );
+    }
+}
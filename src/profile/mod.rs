@@ -0,0 +1,338 @@
+// Module for the execution profiler: where a program spends its instructions
+
+use crate::disasm::disassemble_word;
+use crate::vm::{OpCode, StepResult, VM};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// The approximate number of clock cycles an instruction takes, following the states of
+/// the LC-3 microarchitecture with memory ready on every cycle: 4 to fetch and decode it,
+/// plus its execute states.
+pub fn cycles(opcode: OpCode) -> u64 {
+    const FETCH_AND_DECODE: u64 = 4;
+    let execute = match opcode {
+        OpCode::Add | OpCode::And | OpCode::Not | OpCode::Lea | OpCode::Jmp => 1,
+        OpCode::Br | OpCode::Jsr => 2,
+        OpCode::Ld | OpCode::Ldr | OpCode::St | OpCode::Str => 3,
+        OpCode::Trap => 4,
+        OpCode::Ldi | OpCode::Sti => 5,
+        OpCode::Rti => 8,
+        OpCode::Res => 0,
+    };
+    FETCH_AND_DECODE + execute
+}
+
+/// What the profiler counted for one subroutine, keyed by its start address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    /// Instructions executed from the call to the return, including nested calls.
+    pub instructions: u64,
+    /// Instructions executed in the subroutine itself.
+    pub self_instructions: u64,
+}
+
+/* a subroutine call that has not returned yet */
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    subroutine: u16,
+    return_address: u16,
+    /* the instruction count when it was called */
+    called_at: u64,
+}
+
+/// Counts executions per address, per opcode and per subroutine.
+///
+/// Subroutines are entered by `JSR`/`JSRR` and left by the `RET` (`JMP R7`) that goes
+/// back to the address after the call. Feed it the instructions as they execute, e.g.
+/// from `VM::run_until`.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    addresses: Vec<u64>,
+    opcodes: [u64; 16],
+    instructions: u64,
+    cycles: u64,
+    subroutines: BTreeMap<u16, SubroutineProfile>,
+    stack: Vec<Frame>,
+    /* instructions executed outside of any subroutine */
+    top_level: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            addresses: vec![0; 1 << 16],
+            opcodes: [0; 16],
+            instructions: 0,
+            cycles: 0,
+            subroutines: BTreeMap::new(),
+            stack: Vec::new(),
+            top_level: 0,
+        }
+    }
+
+    /// Counts an executed instruction.
+    pub fn record(&mut self, result: &StepResult) {
+        self.addresses[result.pc_before as usize] += 1;
+        self.opcodes[u16::from(result.opcode) as usize] += 1;
+        self.instructions += 1;
+        self.cycles += cycles(result.opcode);
+        match self.stack.last() {
+            Some(frame) => self.subroutine(frame.subroutine).self_instructions += 1,
+            None => self.top_level += 1,
+        }
+
+        let base_r = (result.instruction >> 6) & 0x7;
+        match result.opcode {
+            OpCode::Jsr if result.exception.is_none() => {
+                self.subroutine(result.pc_after).calls += 1;
+                self.stack.push(Frame {
+                    subroutine: result.pc_after,
+                    return_address: result.pc_before.wrapping_add(1),
+                    called_at: self.instructions,
+                });
+            }
+            OpCode::Jmp if base_r == 7 => {
+                /* a RET returns from the innermost call made from where it goes back to */
+                let returning = self
+                    .stack
+                    .iter()
+                    .rposition(|frame| frame.return_address == result.pc_after);
+                if let Some(depth) = returning {
+                    for frame in self.stack.split_off(depth) {
+                        let instructions = self.instructions - frame.called_at;
+                        self.subroutine(frame.subroutine).instructions += instructions;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn subroutine(&mut self, address: u16) -> &mut SubroutineProfile {
+        self.subroutines.entry(address).or_default()
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The approximate number of clock cycles taken, see [`cycles`].
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn count_at(&self, address: u16) -> u64 {
+        self.addresses[address as usize]
+    }
+
+    pub fn opcode_count(&self, opcode: OpCode) -> u64 {
+        self.opcodes[u16::from(opcode) as usize]
+    }
+
+    /// The subroutines called so far, by start address. Calls that have not returned
+    /// yet count the instructions executed until now.
+    pub fn subroutines(&self) -> BTreeMap<u16, SubroutineProfile> {
+        let mut subroutines = self.subroutines.clone();
+        for frame in &self.stack {
+            let open = subroutines.entry(frame.subroutine).or_default();
+            open.instructions += self.instructions - frame.called_at;
+        }
+        subroutines
+    }
+
+    /// A report ranking the `top` most executed addresses, the opcodes and the subroutines,
+    /// using the symbols and memory of `vm` to show where the instructions are.
+    pub fn report(&self, vm: &VM, top: usize) -> String {
+        let mut report = String::new();
+        let _ = self.write_report(&mut report, vm, top);
+        report
+    }
+
+    fn write_report(&self, out: &mut String, vm: &VM, top: usize) -> std::fmt::Result {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        writeln!(
+            out,
+            "Profile: {} instructions, ~{} cycles",
+            self.instructions, self.cycles
+        )?;
+
+        writeln!(out, "\nHot spots:")?;
+        let mut addresses: Vec<(u16, u64)> = (0..=u16::MAX)
+            .map(|address| (address, self.count_at(address)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(address, count) in addresses.iter().take(top) {
+            let word = vm.read_memory(address);
+            writeln!(
+                out,
+                "  {:>10} {:>6.2}%  {:<16} {}",
+                count,
+                percent(count),
+                vm.symbols().format_address(address),
+                disassemble_word(address, word, vm.symbols())
+            )?;
+        }
+
+        writeln!(out, "\nOpcodes:")?;
+        let mut opcodes: Vec<(OpCode, u64)> = (0..16)
+            .filter_map(|value| OpCode::try_from(value).ok())
+            .map(|opcode| (opcode, self.opcode_count(opcode)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        opcodes.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        for (opcode, count) in opcodes {
+            writeln!(
+                out,
+                "  {:<5} {:>10} {:>6.2}%",
+                opcode.name(),
+                count,
+                percent(count)
+            )?;
+        }
+
+        let mut subroutines: Vec<(u16, SubroutineProfile)> =
+            self.subroutines().into_iter().collect();
+        if subroutines.is_empty() {
+            return Ok(());
+        }
+        subroutines.sort_by_key(|(_, profile)| std::cmp::Reverse(profile.instructions));
+        writeln!(out, "\nSubroutines:")?;
+        writeln!(
+            out,
+            "  {:>8} {:>12} {:>12}  subroutine",
+            "calls", "instructions", "self"
+        )?;
+        for (address, profile) in subroutines.iter().take(top) {
+            writeln!(
+                out,
+                "  {:>8} {:>12} {:>12}  {}",
+                profile.calls,
+                profile.instructions,
+                profile.self_instructions,
+                vm.symbols().format_address(*address)
+            )?;
+        }
+        writeln!(
+            out,
+            "  {:>8} {:>12} {:>12}  (outside subroutines)",
+            "", "", self.top_level
+        )
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::MemoryConsole;
+
+    fn profile(source: &str) -> (VM, Profiler) {
+        let assembly = assemble(source).unwrap();
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(&assembly.image);
+        vm.symbols_mut().extend(&assembly.symbols);
+        let mut profiler = Profiler::new();
+        vm.run_until(|_, result| {
+            profiler.record(result);
+            false
+        })
+        .unwrap();
+        (vm, profiler)
+    }
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #3
+AGAIN   JSR TWICE
+        ADD R1, R1, #-1
+        BRp AGAIN
+        HALT
+TWICE   ST R7, SAVE_R7
+        JSR ONCE
+        JSR ONCE
+        LD R7, SAVE_R7
+        RET
+ONCE    ADD R0, R0, #1
+        RET
+SAVE_R7 .FILL x0000
+        .END";
+
+    #[test]
+    fn test_counts() {
+        let (_, profiler) = profile(PROGRAM);
+
+        /* 12 instructions outside subroutines, and 9 per call to TWICE */
+        assert_eq!(profiler.instructions(), 12 + 3 * 9);
+        assert_eq!(profiler.count_at(0x3002), 3);
+        assert_eq!(profiler.count_at(0x300B), 6);
+        assert_eq!(profiler.opcode_count(OpCode::Jsr), 9);
+        assert_eq!(profiler.opcode_count(OpCode::Jmp), 9);
+        assert_eq!(profiler.opcode_count(OpCode::Add), 10);
+        assert_eq!(
+            profiler.cycles(),
+            39 * 4 + 1 + 10 + 9 * 2 + 3 * 2 + 4 + 3 * 3 + 3 * 3 + 9
+        );
+    }
+
+    #[test]
+    fn test_subroutines() {
+        let (_, profiler) = profile(PROGRAM);
+        let subroutines = profiler.subroutines();
+
+        assert_eq!(
+            subroutines[&0x3006],
+            SubroutineProfile {
+                calls: 3,
+                instructions: 27,
+                self_instructions: 15,
+            }
+        );
+        assert_eq!(
+            subroutines[&0x300B],
+            SubroutineProfile {
+                calls: 6,
+                instructions: 12,
+                self_instructions: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn test_unreturned_calls_count_so_far() {
+        let assembly = assemble(PROGRAM).unwrap();
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(&assembly.image);
+        let mut profiler = Profiler::new();
+        for _ in 0..5 {
+            profiler.record(&vm.step().unwrap());
+        }
+
+        /* stopped in ONCE, called from TWICE */
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines[&0x3006].instructions, 2);
+        assert_eq!(subroutines[&0x300B].instructions, 0);
+        assert_eq!(subroutines[&0x300B].calls, 1);
+    }
+
+    #[test]
+    fn test_report() {
+        let (vm, profiler) = profile(PROGRAM);
+        let report = profiler.report(&vm, 2);
+
+        assert!(report.starts_with("Profile: 39 instructions, ~222 cycles\n"));
+        assert!(report
+            .contains("\nHot spots:\n           6  15.38%  ONCE             ADD R0, R0, #1\n"));
+        assert!(report.contains("  ADD           10  25.64%\n"));
+        assert!(report.contains("         3           27           15  TWICE\n"));
+        assert!(report.contains("                           12  (outside subroutines)\n"));
+    }
+}
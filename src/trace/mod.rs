@@ -0,0 +1,232 @@
+// Module for execution traces: one JSON object per executed instruction
+
+use crate::disasm::disassemble_word;
+use crate::vm::{OpCode, Register, StepResult, VM};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Which executed instructions make it into a trace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only instructions fetched from `start..=end`, when set.
+    pub range: Option<(u16, u16)>,
+    /// Only these opcodes, when not empty.
+    pub opcodes: Vec<OpCode>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, result: &StepResult) -> bool {
+        let in_range = self.range.map_or(true, |(start, end)| {
+            (start..=end).contains(&result.pc_before)
+        });
+        in_range && (self.opcodes.is_empty() || self.opcodes.contains(&result.opcode))
+    }
+}
+
+/// Writes a trace in the JSON Lines format, one line per executed instruction.
+///
+/// Feed it the instructions as they execute, e.g. from `VM::run_until`:
+///
+/// ```no_run
+/// # use lc3_vm::{trace::Tracer, VM};
+/// let mut vm = VM::new();
+/// let mut tracer = Tracer::new(std::io::stdout());
+/// vm.run_until(|vm, result| tracer.record(vm, result).is_err())?;
+/// # Ok::<(), lc3_vm::VmError>(())
+/// ```
+pub struct Tracer<W: Write> {
+    output: W,
+    filter: TraceFilter,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W) -> Self {
+        Self::with_filter(output, TraceFilter::default())
+    }
+
+    pub fn with_filter(output: W, filter: TraceFilter) -> Self {
+        Tracer { output, filter }
+    }
+
+    /// Writes the line for an instruction `vm` just executed, unless it is filtered out.
+    pub fn record(&mut self, vm: &VM, result: &StepResult) -> io::Result<()> {
+        if !self.filter.matches(result) {
+            return Ok(());
+        }
+        writeln!(self.output, "{}", trace_entry(vm, result))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+/// The JSON object describing an instruction `vm` just executed.
+///
+/// `step` counts the instructions executed so far, `regs` lists the general purpose
+/// registers written and `cond` is the condition code after the instruction. Addresses
+/// and values are written as LC-3 hex strings (`"x3000"`), so traces diff easily.
+pub fn trace_entry(vm: &VM, result: &StepResult) -> String {
+    let mut entry = format!(
+        "{{\"step\":{},\"pc\":\"x{:04X}\",\"word\":\"x{:04X}\",\"op\":\"{}\",\"asm\":\"{}\"",
+        vm.instruction_count(),
+        result.pc_before,
+        result.instruction,
+        result.opcode.name(),
+        escape(&disassemble_word(
+            result.pc_before,
+            result.instruction,
+            vm.symbols()
+        ))
+    );
+
+    let regs: Vec<String> = result
+        .register_writes
+        .iter()
+        .filter(|write| write.register != Register::Cond)
+        .map(|write| {
+            format!(
+                "{{\"reg\":\"{:?}\",\"old\":\"x{:04X}\",\"new\":\"x{:04X}\"}}",
+                write.register, write.old, write.new
+            )
+        })
+        .collect();
+    let reads: Vec<String> = result
+        .memory_reads
+        .iter()
+        .map(|read| {
+            format!(
+                "{{\"addr\":\"x{:04X}\",\"value\":\"x{:04X}\"}}",
+                read.address, read.value
+            )
+        })
+        .collect();
+    let writes: Vec<String> = result
+        .memory_writes
+        .iter()
+        .map(|write| {
+            format!(
+                "{{\"addr\":\"x{:04X}\",\"old\":\"x{:04X}\",\"new\":\"x{:04X}\"}}",
+                write.address, write.old, write.new
+            )
+        })
+        .collect();
+    let cond = vm.cond().map_or("?".to_string(), |flag| flag.to_string());
+    let _ = write!(
+        entry,
+        ",\"regs\":[{}],\"cond\":\"{}\",\"reads\":[{}],\"writes\":[{}]",
+        regs.join(","),
+        cond,
+        reads.join(","),
+        writes.join(",")
+    );
+
+    if let Some(vector) = result.trap {
+        let _ = write!(entry, ",\"trap\":\"x{:02X}\"", vector);
+    }
+    if let Some(vector) = result.interrupt {
+        let _ = write!(entry, ",\"interrupt\":\"x{:02X}\"", vector);
+    }
+    if let Some(vector) = result.exception {
+        let _ = write!(entry, ",\"exception\":\"x{:02X}\"", vector);
+    }
+    if result.halted {
+        entry.push_str(",\"halted\":true");
+    }
+    entry.push('}');
+    entry
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::MemoryConsole;
+
+    fn load(source: &str) -> VM {
+        let assembly = assemble(source).unwrap();
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(&assembly.image);
+        vm.symbols_mut().extend(&assembly.symbols);
+        vm
+    }
+
+    fn trace(vm: &mut VM, filter: TraceFilter) -> Vec<String> {
+        let mut tracer = Tracer::with_filter(Vec::new(), filter);
+        vm.run_until(|vm, result| tracer.record(vm, result).is_err())
+            .unwrap();
+        String::from_utf8(tracer.into_inner())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+        ADD R1, R1, #1
+        ST R1, COUNT
+        HALT
+COUNT   .FILL x0007
+        .END";
+
+    #[test]
+    fn test_trace_entries() {
+        let mut vm = load(PROGRAM);
+        let lines = trace(&mut vm, TraceFilter::default());
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "{\"step\":1,\"pc\":\"x3000\",\"word\":\"x2203\",\"op\":\"LD\",\
+             \"asm\":\"LD R1, COUNT\",\"regs\":[{\"reg\":\"R1\",\"old\":\"x0000\",\"new\":\"x0007\"}],\
+             \"cond\":\"P\",\"reads\":[{\"addr\":\"x3004\",\"value\":\"x0007\"}],\"writes\":[]}"
+        );
+        assert_eq!(
+            lines[2],
+            "{\"step\":3,\"pc\":\"x3002\",\"word\":\"x3201\",\"op\":\"ST\",\
+             \"asm\":\"ST R1, COUNT\",\"regs\":[],\"cond\":\"P\",\"reads\":[],\
+             \"writes\":[{\"addr\":\"x3004\",\"old\":\"x0007\",\"new\":\"x0008\"}]}"
+        );
+        assert!(lines[3].ends_with(",\"trap\":\"x25\",\"halted\":true}"));
+    }
+
+    #[test]
+    fn test_trace_filters() {
+        let mut vm = load(PROGRAM);
+        let filter = TraceFilter {
+            range: Some((0x3001, 0x3003)),
+            opcodes: vec![OpCode::Add, OpCode::Trap],
+        };
+        let lines = trace(&mut vm, filter);
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"op\":\"ADD\""));
+        assert!(lines[1].contains("\"op\":\"TRAP\""));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+    }
+}
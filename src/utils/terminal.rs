@@ -3,26 +3,46 @@ extern crate termios;
 use libc::STDIN_FILENO;
 use signal_hook::{iterator::Signals, SIGINT};
 use std::process;
-use std::{error::Error, thread};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{error::Error, io, thread};
 use termios::*;
 
+/* the settings to put back on Ctrl-C, while the terminal is out of canonical mode */
+static SAVED_SETTINGS: Mutex<Option<Termios>> = Mutex::new(None);
+
 fn handle_control_c(_sig: i32) {
-    restore_terminal_settings();
+    if let Some(settings) = SAVED_SETTINGS
+        .lock()
+        .ok()
+        .and_then(|mut saved| saved.take())
+    {
+        let _ = tcsetattr(STDIN_FILENO, TCSANOW, &settings);
+    }
     println!("\n\n");
     println!("The LC3 VM received Ctrl-C interrupt signal.");
     process::exit(130);
 }
 
-pub fn restore_terminal_settings() {
-    let mut term: Termios = Termios::from_fd(STDIN_FILENO).unwrap();
-    term.c_lflag |= ICANON | ECHO;
-    tcsetattr(STDIN_FILENO, TCSANOW, &term).unwrap();
+pub(crate) fn is_tty() -> bool {
+    unsafe { libc::isatty(STDIN_FILENO) == 1 }
 }
 
-pub fn turn_off_canonical_and_echo_modes() {
-    let mut term: Termios = Termios::from_fd(STDIN_FILENO).unwrap();
+/// Returns the settings the terminal had, for `restore_terminal`. Ctrl-C puts them
+/// back too until then. Fails when stdin is not a terminal.
+pub(crate) fn turn_off_canonical_and_echo_modes() -> io::Result<Termios> {
+    let original: Termios = Termios::from_fd(STDIN_FILENO)?;
+    let mut term = original;
     term.c_lflag &= !(ICANON | ECHO);
-    tcsetattr(STDIN_FILENO, TCSANOW, &term).unwrap();
+    *SAVED_SETTINGS.lock().unwrap() = Some(original);
+    tcsetattr(STDIN_FILENO, TCSANOW, &term)?;
+    Ok(original)
+}
+
+/// Puts back settings returned by `turn_off_canonical_and_echo_modes`.
+pub(crate) fn restore_terminal(settings: &Termios) -> io::Result<()> {
+    SAVED_SETTINGS.lock().unwrap().take();
+    tcsetattr(STDIN_FILENO, TCSANOW, settings)
 }
 
 pub fn spawn_control_c_handler() -> Result<(), Box<dyn Error>> {
@@ -34,3 +54,19 @@ pub fn spawn_control_c_handler() -> Result<(), Box<dyn Error>> {
     });
     Ok(())
 }
+
+/// Like `spawn_control_c_handler`, but the first Ctrl-C only raises the returned flag,
+/// so the caller can stop cleanly. A second one exits as usual.
+pub fn spawn_control_c_flag() -> Result<Arc<AtomicBool>, Box<dyn Error>> {
+    let signals = Signals::new([SIGINT])?;
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&interrupted);
+    thread::spawn(move || {
+        for sig in signals.forever() {
+            if flag.swap(true, Ordering::SeqCst) {
+                handle_control_c(sig);
+            }
+        }
+    });
+    Ok(interrupted)
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConditionFlag {
     Pos = 1 << 0, /* P */
     Zro = 1 << 1, /* Z */
@@ -23,3 +25,14 @@ impl TryFrom<u16> for ConditionFlag {
         })
     }
 }
+
+impl fmt::Display for ConditionFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Pos => "P",
+            Self::Zro => "Z",
+            Self::Neg => "N",
+        };
+        f.write_str(name)
+    }
+}
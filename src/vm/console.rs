@@ -0,0 +1,321 @@
+// Module for the console the VM reads its input from and writes its output to
+
+use crate::utils::terminal;
+use libc::{POLLIN, STDIN_FILENO};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+use termios::Termios;
+
+/// Character I/O used by the trap routines and the keyboard registers.
+pub trait Console {
+    /// Returns whether a byte of input can be read without blocking.
+    fn key_available(&mut self) -> io::Result<bool>;
+
+    /// Blocks until a byte of input is available and returns it.
+    /// Returns an `UnexpectedEof` error once the input is exhausted.
+    fn read_byte(&mut self) -> io::Result<u8>;
+
+    /// Returns whether the input is exhausted for good: no byte is available and none
+    /// ever will be. The keyboard then shows a key whose read fails like `read_byte`.
+    fn input_ended(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Hands the underlying terminal back to the host (e.g. for a debugger prompt)
+    /// until the next input is needed.
+    fn suspend(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for &byte in bytes {
+            self.write_byte(byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The process terminal: reads from stdin, writes to stdout.
+///
+/// The first time input is needed the terminal is switched out of canonical
+/// mode and echo, so single key presses can be polled; the settings it had
+/// before are restored exactly when the console is suspended or dropped.
+#[derive(Debug, Default)]
+pub struct TerminalConsole {
+    input: VecDeque<u8>,
+    /* the settings to restore, while the terminal is in raw mode */
+    saved_settings: Option<Termios>,
+    eof: bool,
+}
+
+impl TerminalConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn enter_raw_mode(&mut self) {
+        if self.saved_settings.is_none() && terminal::is_tty() {
+            self.saved_settings = terminal::turn_off_canonical_and_echo_modes().ok();
+        }
+    }
+
+    /* reads whatever is available on stdin into the input queue, blocking if asked to */
+    fn fill_input(&mut self, block: bool) -> io::Result<()> {
+        self.enter_raw_mode();
+        if self.eof {
+            return Ok(());
+        }
+        if !block {
+            let mut fds = libc::pollfd {
+                fd: STDIN_FILENO,
+                events: POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut fds, 1, 0) };
+            if ready < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if ready == 0 {
+                return Ok(());
+            }
+        }
+        let mut buffer = [0u8; 64];
+        let read = unsafe {
+            libc::read(
+                STDIN_FILENO,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if read == 0 {
+            self.eof = true;
+        }
+        self.input.extend(&buffer[..read as usize]);
+        Ok(())
+    }
+}
+
+impl Drop for TerminalConsole {
+    fn drop(&mut self) {
+        if let Some(settings) = &self.saved_settings {
+            let _ = terminal::restore_terminal(settings);
+        }
+    }
+}
+
+impl Console for TerminalConsole {
+    fn key_available(&mut self) -> io::Result<bool> {
+        if self.input.is_empty() {
+            self.fill_input(false)?;
+        }
+        Ok(!self.input.is_empty())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        while self.input.is_empty() && !self.eof {
+            self.fill_input(true)?;
+        }
+        self.input
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn input_ended(&mut self) -> io::Result<bool> {
+        Ok(!self.key_available()? && self.eof)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+    fn suspend(&mut self) -> io::Result<()> {
+        if let Some(settings) = self.saved_settings.take() {
+            terminal::restore_terminal(&settings)?;
+        }
+        self.flush()
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryConsoleState {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+/// An in-memory console for running programs headlessly.
+///
+/// Clones share the same buffers, so a clone can be kept to feed input and
+/// inspect the output while the VM owns the console.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryConsole {
+    state: Rc<RefCell<MemoryConsoleState>>,
+}
+
+impl MemoryConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a console whose input is `input`.
+    pub fn with_input(input: &[u8]) -> Self {
+        let console = Self::new();
+        console.push_input(input);
+        console
+    }
+
+    /// Appends `input` to the bytes still to be read.
+    pub fn push_input(&self, input: &[u8]) {
+        self.state.borrow_mut().input.extend(input);
+    }
+
+    /// Everything written so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.state.borrow().output.clone()
+    }
+
+    /// Everything written so far, lossily converted to a string.
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.state.borrow().output).into_owned()
+    }
+
+    /// Discards everything written so far.
+    pub fn clear_output(&self) {
+        self.state.borrow_mut().output.clear();
+    }
+}
+
+impl Console for MemoryConsole {
+    fn key_available(&mut self) -> io::Result<bool> {
+        Ok(!self.state.borrow().input.is_empty())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.state
+            .borrow_mut()
+            .input
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.state.borrow_mut().output.push(byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A console for batch runs: its input is given up front and its output goes to any
+/// writer (a file, stdout...). It never touches the terminal settings.
+///
+/// Once the input is exhausted, reading fails with `UnexpectedEof` instead of blocking.
+#[derive(Debug)]
+pub struct StreamConsole<W: Write> {
+    input: VecDeque<u8>,
+    output: W,
+}
+
+impl<W: Write> StreamConsole<W> {
+    pub fn new(input: &[u8], output: W) -> Self {
+        StreamConsole {
+            input: input.iter().copied().collect(),
+            output,
+        }
+    }
+
+    /// Gives back the writer, e.g. to inspect a `Vec<u8>` output.
+    pub fn into_output(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> Console for StreamConsole<W> {
+    fn key_available(&mut self) -> io::Result<bool> {
+        Ok(!self.input.is_empty())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.input
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn input_ended(&mut self) -> io::Result<bool> {
+        Ok(self.input.is_empty())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.write_all(&[byte])
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_console() {
+        let handle = MemoryConsole::with_input(b"ab");
+        let mut console = handle.clone();
+
+        assert!(console.key_available().unwrap());
+        assert_eq!(console.read_byte().unwrap(), b'a');
+        handle.push_input(b"c");
+        assert_eq!(console.read_byte().unwrap(), b'b');
+        assert_eq!(console.read_byte().unwrap(), b'c');
+        assert_eq!(
+            console.read_byte().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(!console.key_available().unwrap());
+
+        console.write_bytes(b"Hi").unwrap();
+        assert_eq!(handle.output(), b"Hi");
+        assert_eq!(handle.output_string(), "Hi");
+        handle.clear_output();
+        assert!(handle.output().is_empty());
+    }
+
+    #[test]
+    fn test_stream_console() {
+        let mut console = StreamConsole::new(b"a", Vec::new());
+
+        assert!(console.key_available().unwrap());
+        assert!(!console.input_ended().unwrap());
+        assert_eq!(console.read_byte().unwrap(), b'a');
+        assert!(!console.key_available().unwrap());
+        assert!(console.input_ended().unwrap());
+        assert_eq!(
+            console.read_byte().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        console.write_byte(b'H').unwrap();
+        console.write_bytes(b"i!").unwrap();
+        console.flush().unwrap();
+        assert_eq!(console.into_output(), b"Hi!");
+    }
+}
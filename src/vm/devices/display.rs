@@ -0,0 +1,65 @@
+// Module for the display: DSR and DDR
+
+use super::{Device, DeviceContext};
+use crate::vm::{MemoryMappedRegister, VmError};
+
+/* display status register: ready for the next character */
+const DSR_READY: u16 = 1 << 15;
+
+/// The display, writing characters to the console.
+///
+/// Output is written straight away, so DSR always reports the display as ready.
+/// Writing DDR prints the character in its low byte.
+#[derive(Debug, Clone, Default)]
+pub struct Display {
+    data: u16,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Device for Display {
+    fn addresses(&self) -> Vec<u16> {
+        vec![
+            MemoryMappedRegister::Dsr.into(),
+            MemoryMappedRegister::Ddr.into(),
+        ]
+    }
+
+    fn read(&mut self, address: u16, _context: &mut DeviceContext) -> Result<u16, VmError> {
+        Ok(self.peek(address))
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        context: &mut DeviceContext,
+    ) -> Result<(), VmError> {
+        if address == MemoryMappedRegister::Ddr.into() {
+            self.data = value;
+            context.console.write_byte(value as u8)?;
+            context.console.flush()?;
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        if address == MemoryMappedRegister::Dsr.into() {
+            DSR_READY
+        } else {
+            self.data
+        }
+    }
+
+    fn save_state(&self) -> Vec<u16> {
+        vec![self.data]
+    }
+
+    fn restore_state(&mut self, state: &[u16]) {
+        self.data = state[0];
+    }
+}
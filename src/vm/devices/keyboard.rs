@@ -0,0 +1,103 @@
+// Module for the keyboard: KBSR and KBDR
+
+use super::{Device, DeviceContext};
+use crate::vm::{Interrupt, MemoryMappedRegister, VmError, KEYBOARD_PRIORITY, KEYBOARD_VECTOR};
+
+/* keyboard status register bits */
+const KBSR_READY: u16 = 1 << 15;
+const KBSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+/// The keyboard, reading keys from the console.
+///
+/// KBSR bit 15 tells whether a key is waiting, bit 14 enables keyboard interrupts.
+/// Reading KBDR takes the waiting key. Once the console's input has ended, a key is
+/// always waiting and reading it fails, so a program reading past the end of its input
+/// stops as it would with the `GETC` of the VM.
+#[derive(Debug, Clone, Default)]
+pub struct Keyboard {
+    interrupt_enable: bool,
+    /* what KBSR and KBDR read last */
+    status: u16,
+    data: u16,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/* whether KBSR shows a key: one is waiting, or the input has ended */
+fn ready(context: &mut DeviceContext) -> Result<bool, VmError> {
+    Ok(context.console.key_available()? || context.console.input_ended()?)
+}
+
+impl Device for Keyboard {
+    fn addresses(&self) -> Vec<u16> {
+        vec![
+            MemoryMappedRegister::Kbsr.into(),
+            MemoryMappedRegister::Kbddr.into(),
+        ]
+    }
+
+    fn read(&mut self, address: u16, context: &mut DeviceContext) -> Result<u16, VmError> {
+        let key_available = ready(context)?;
+        if address == MemoryMappedRegister::Kbddr.into() {
+            if key_available {
+                self.data = context.console.read_byte()? as u16;
+            }
+            self.status &= !KBSR_READY;
+            return Ok(self.data);
+        }
+        self.status = if key_available { KBSR_READY } else { 0 };
+        if self.interrupt_enable {
+            self.status |= KBSR_INTERRUPT_ENABLE;
+        }
+        Ok(self.status)
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        _context: &mut DeviceContext,
+    ) -> Result<(), VmError> {
+        /* only the interrupt enable bit of KBSR is writable */
+        if address == MemoryMappedRegister::Kbsr.into() {
+            self.interrupt_enable = value & KBSR_INTERRUPT_ENABLE != 0;
+            self.status = (self.status & KBSR_READY) | (value & KBSR_INTERRUPT_ENABLE);
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        if address == MemoryMappedRegister::Kbsr.into() {
+            self.status
+        } else {
+            self.data
+        }
+    }
+
+    fn pending_interrupt(
+        &mut self,
+        context: &mut DeviceContext,
+    ) -> Result<Option<Interrupt>, VmError> {
+        if self.interrupt_enable && ready(context)? {
+            return Ok(Some(Interrupt {
+                vector: KEYBOARD_VECTOR,
+                priority: KEYBOARD_PRIORITY,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn save_state(&self) -> Vec<u16> {
+        vec![self.interrupt_enable as u16, self.status, self.data]
+    }
+
+    fn restore_state(&mut self, state: &[u16]) {
+        self.interrupt_enable = state[0] != 0;
+        self.status = state[1];
+        self.data = state[2];
+    }
+}
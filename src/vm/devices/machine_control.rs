@@ -0,0 +1,62 @@
+// Module for the machine control register, MCR
+
+use super::{Device, DeviceContext};
+use crate::vm::{MemoryMappedRegister, VmError};
+
+/* the clock runs while this bit is set */
+const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+
+/// The machine control register. Clearing its clock enable bit (15) halts the machine.
+#[derive(Debug, Clone)]
+pub struct MachineControl {
+    value: u16,
+}
+
+impl MachineControl {
+    pub fn new() -> Self {
+        MachineControl {
+            value: MCR_CLOCK_ENABLE,
+        }
+    }
+}
+
+impl Default for MachineControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for MachineControl {
+    fn addresses(&self) -> Vec<u16> {
+        vec![MemoryMappedRegister::Mcr.into()]
+    }
+
+    fn read(&mut self, _address: u16, _context: &mut DeviceContext) -> Result<u16, VmError> {
+        Ok(self.value)
+    }
+
+    fn write(
+        &mut self,
+        _address: u16,
+        value: u16,
+        context: &mut DeviceContext,
+    ) -> Result<(), VmError> {
+        self.value = value;
+        if value & MCR_CLOCK_ENABLE == 0 {
+            *context.running = false;
+        }
+        Ok(())
+    }
+
+    fn peek(&self, _address: u16) -> u16 {
+        self.value
+    }
+
+    fn save_state(&self) -> Vec<u16> {
+        vec![self.value]
+    }
+
+    fn restore_state(&mut self, state: &[u16]) {
+        self.value = state[0];
+    }
+}
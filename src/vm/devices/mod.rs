@@ -0,0 +1,192 @@
+// Module for memory mapped devices and the bus routing the device page to them
+
+mod display;
+mod keyboard;
+mod machine_control;
+mod timer;
+
+pub use display::Display;
+pub use keyboard::Keyboard;
+pub use machine_control::MachineControl;
+pub use timer::{Timer, TIMER_PRIORITY, TIMER_VECTOR};
+
+use super::{Console, Interrupt, VmError};
+
+/// Start of the device page, xFE00-xFFFF.
+pub const DEVICE_PAGE_START: u16 = 0xFE00;
+
+const DEVICE_PAGE_SIZE: usize = 0x200;
+
+/// What a device can reach of the machine it is plugged into.
+pub struct DeviceContext<'a> {
+    pub console: &'a mut dyn Console,
+    /// Set to false to stop the machine.
+    pub running: &'a mut bool,
+}
+
+/// A peripheral with registers mapped into the device page.
+pub trait Device {
+    /// The addresses of the device's registers, all within xFE00-xFFFF.
+    fn addresses(&self) -> Vec<u16>;
+
+    /// A read of one of the device's registers by the program.
+    fn read(&mut self, address: u16, context: &mut DeviceContext) -> Result<u16, VmError>;
+
+    /// A write to one of the device's registers by the program.
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        context: &mut DeviceContext,
+    ) -> Result<(), VmError>;
+
+    /// The value of a register, as a debugger would show it, without side effects.
+    fn peek(&self, address: u16) -> u16;
+
+    /// Called after every instruction.
+    fn tick(&mut self, _context: &mut DeviceContext) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    /// The interrupt the device requests, if any. Checked before every instruction.
+    fn pending_interrupt(
+        &mut self,
+        _context: &mut DeviceContext,
+    ) -> Result<Option<Interrupt>, VmError> {
+        Ok(None)
+    }
+
+    /// The device's internal state, saved in VM snapshots. Stateless devices keep the default.
+    fn save_state(&self) -> Vec<u16> {
+        Vec::new()
+    }
+
+    /// Restores a state returned by `save_state`, which always has the same length.
+    fn restore_state(&mut self, _state: &[u16]) {}
+}
+
+/// The devices plugged into the machine, and which of them owns each device page address.
+pub(crate) struct Bus {
+    devices: Vec<Box<dyn Device>>,
+    owners: [Option<usize>; DEVICE_PAGE_SIZE],
+}
+
+impl Bus {
+    pub(crate) fn new() -> Self {
+        Bus {
+            devices: Vec::new(),
+            owners: [None; DEVICE_PAGE_SIZE],
+        }
+    }
+
+    /// Plugs in a device. Its registers take over from any device attached before it,
+    /// and a device left without any register is unplugged: it no longer ticks or
+    /// raises interrupts.
+    ///
+    /// Panics if the device claims an address outside the device page.
+    pub(crate) fn attach(&mut self, device: Box<dyn Device>) {
+        let index = self.devices.len();
+        let mut displaced = Vec::new();
+        for address in device.addresses() {
+            assert!(
+                address >= DEVICE_PAGE_START,
+                "device register x{:04X} is outside the device page",
+                address
+            );
+            let owner = &mut self.owners[(address - DEVICE_PAGE_START) as usize];
+            displaced.extend(owner.replace(index));
+        }
+        self.devices.push(device);
+
+        let unplugged: Vec<usize> = displaced
+            .into_iter()
+            .filter(|&old| !self.owners.contains(&Some(old)))
+            .collect();
+        if unplugged.is_empty() {
+            return;
+        }
+        /* the devices left move down, so their registers follow them */
+        let mut new_index = vec![0; self.devices.len()];
+        for (old, device) in std::mem::take(&mut self.devices).into_iter().enumerate() {
+            if !unplugged.contains(&old) {
+                new_index[old] = self.devices.len();
+                self.devices.push(device);
+            }
+        }
+        for owner in self.owners.iter_mut().flatten() {
+            *owner = new_index[*owner];
+        }
+    }
+
+    pub(crate) fn device_at(&mut self, address: u16) -> Option<&mut dyn Device> {
+        let index = self.owner(address)?;
+        Some(self.devices[index].as_mut())
+    }
+
+    pub(crate) fn peek(&self, address: u16) -> Option<u16> {
+        let index = self.owner(address)?;
+        Some(self.devices[index].peek(address))
+    }
+
+    fn owner(&self, address: u16) -> Option<usize> {
+        let offset = address.checked_sub(DEVICE_PAGE_START)?;
+        self.owners[offset as usize]
+    }
+
+    pub(crate) fn save_states(&self) -> Vec<Vec<u16>> {
+        self.devices
+            .iter()
+            .map(|device| device.save_state())
+            .collect()
+    }
+
+    /// Restores the states returned by `save_states`, checking first that they fit the
+    /// devices attached.
+    pub(crate) fn restore_states(&mut self, states: &[Vec<u16>]) -> Result<(), String> {
+        if states.len() != self.devices.len() {
+            return Err(format!(
+                "{} devices saved, but {} attached",
+                states.len(),
+                self.devices.len()
+            ));
+        }
+        for (index, (device, state)) in self.devices.iter().zip(states).enumerate() {
+            let expected = device.save_state().len();
+            if state.len() != expected {
+                return Err(format!(
+                    "device {} has {} words of state saved, expected {}",
+                    index,
+                    state.len(),
+                    expected
+                ));
+            }
+        }
+        for (device, state) in self.devices.iter_mut().zip(states) {
+            device.restore_state(state);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tick(&mut self, context: &mut DeviceContext) -> Result<(), VmError> {
+        for device in &mut self.devices {
+            device.tick(context)?;
+        }
+        Ok(())
+    }
+
+    /// The highest priority interrupt requested by any device.
+    pub(crate) fn pending_interrupt(
+        &mut self,
+        context: &mut DeviceContext,
+    ) -> Result<Option<Interrupt>, VmError> {
+        let mut pending: Option<Interrupt> = None;
+        for device in &mut self.devices {
+            if let Some(interrupt) = device.pending_interrupt(context)? {
+                if pending.map_or(true, |p| interrupt.priority > p.priority) {
+                    pending = Some(interrupt);
+                }
+            }
+        }
+        Ok(pending)
+    }
+}
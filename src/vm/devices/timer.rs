@@ -0,0 +1,210 @@
+// Module for the programmable interval timer
+
+use super::{Device, DeviceContext};
+use crate::vm::{Interrupt, MemoryMappedRegister, VmError};
+use std::time::Instant;
+
+/*
+    TSR, timer status:
+    15      | 14 | 13 ... 2 | 1            | 0
+    expired | IE | 0        | milliseconds | enable
+*/
+pub(crate) const TSR_EXPIRED: u16 = 1 << 15;
+pub(crate) const TSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+pub(crate) const TSR_MILLISECONDS: u16 = 1 << 1;
+pub(crate) const TSR_ENABLE: u16 = 1 << 0;
+
+pub const TIMER_VECTOR: u8 = 0x81;
+pub const TIMER_PRIORITY: u16 = 5;
+
+/// A timer counting down executed instructions or wall-clock milliseconds.
+///
+/// When the counter reaches zero the timer expires, reloads the counter from the
+/// interval and, if enabled, requests an interrupt. The vector and priority are set
+/// through TVR: priority in bits 10-8, vector in bits 7-0.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    status: u16,
+    interval: u16,
+    counter: u16,
+    vector: u16,
+    /* when the last whole millisecond was counted */
+    last_tick: Option<Instant>,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            status: 0,
+            interval: 0,
+            counter: 0,
+            vector: (TIMER_PRIORITY << 8) | TIMER_VECTOR as u16,
+            last_tick: None,
+        }
+    }
+
+    /// Reads TSR, which acknowledges the expiry.
+    fn read_status(&mut self) -> u16 {
+        let status = self.status;
+        self.status &= !TSR_EXPIRED;
+        status
+    }
+
+    fn write_status(&mut self, value: u16) {
+        /* the expired bit is read-only */
+        self.status = (self.status & TSR_EXPIRED) | (value & !TSR_EXPIRED);
+        self.last_tick = None;
+    }
+
+    fn write_interval(&mut self, value: u16) {
+        self.interval = value;
+        self.counter = value;
+    }
+
+    /// Counts one executed instruction, or the milliseconds elapsed since the last tick.
+    fn count(&mut self) {
+        if self.status & TSR_ENABLE == 0 || self.interval == 0 {
+            return;
+        }
+        let elapsed = if self.status & TSR_MILLISECONDS != 0 {
+            let now = Instant::now();
+            let last_tick = *self.last_tick.get_or_insert(now);
+            let millis = now.duration_since(last_tick).as_millis();
+            if millis == 0 {
+                return;
+            }
+            self.last_tick = Some(now);
+            millis
+        } else {
+            1
+        };
+        for _ in 0..elapsed.min(self.interval as u128) {
+            self.counter = self.counter.saturating_sub(1);
+            if self.counter == 0 {
+                self.status |= TSR_EXPIRED;
+                self.counter = self.interval;
+            }
+        }
+    }
+
+    fn requested_interrupt(&self) -> Option<Interrupt> {
+        let requested = TSR_EXPIRED | TSR_INTERRUPT_ENABLE;
+        (self.status & requested == requested).then_some(Interrupt {
+            vector: self.vector as u8,
+            priority: (self.vector >> 8) & 0x7,
+        })
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Timer {
+    fn addresses(&self) -> Vec<u16> {
+        vec![
+            MemoryMappedRegister::Tsr.into(),
+            MemoryMappedRegister::Tir.into(),
+            MemoryMappedRegister::Tcr.into(),
+            MemoryMappedRegister::Tvr.into(),
+        ]
+    }
+
+    fn read(&mut self, address: u16, _context: &mut DeviceContext) -> Result<u16, VmError> {
+        if address == MemoryMappedRegister::Tsr.into() {
+            return Ok(self.read_status());
+        }
+        Ok(self.peek(address))
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        _context: &mut DeviceContext,
+    ) -> Result<(), VmError> {
+        match MemoryMappedRegister::try_from(address) {
+            Ok(MemoryMappedRegister::Tsr) => self.write_status(value),
+            Ok(MemoryMappedRegister::Tir) => self.write_interval(value),
+            Ok(MemoryMappedRegister::Tcr) => self.counter = value,
+            Ok(MemoryMappedRegister::Tvr) => self.vector = value,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        match MemoryMappedRegister::try_from(address) {
+            Ok(MemoryMappedRegister::Tsr) => self.status,
+            Ok(MemoryMappedRegister::Tir) => self.interval,
+            Ok(MemoryMappedRegister::Tcr) => self.counter,
+            _ => self.vector,
+        }
+    }
+
+    fn tick(&mut self, _context: &mut DeviceContext) -> Result<(), VmError> {
+        self.count();
+        Ok(())
+    }
+
+    fn pending_interrupt(
+        &mut self,
+        _context: &mut DeviceContext,
+    ) -> Result<Option<Interrupt>, VmError> {
+        Ok(self.requested_interrupt())
+    }
+
+    fn save_state(&self) -> Vec<u16> {
+        vec![self.status, self.interval, self.counter, self.vector]
+    }
+
+    fn restore_state(&mut self, state: &[u16]) {
+        self.status = state[0];
+        self.interval = state[1];
+        self.counter = state[2];
+        self.vector = state[3];
+        /* milliseconds are counted again from the next tick */
+        self.last_tick = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_instructions() {
+        let mut timer = Timer::new();
+        timer.write_interval(3);
+        timer.write_status(TSR_ENABLE | TSR_INTERRUPT_ENABLE);
+
+        timer.count();
+        timer.count();
+        assert_eq!(timer.counter, 1);
+        assert_eq!(timer.requested_interrupt(), None);
+
+        timer.count();
+        assert_eq!(timer.counter, 3);
+        assert_eq!(
+            timer.requested_interrupt(),
+            Some(Interrupt {
+                vector: TIMER_VECTOR,
+                priority: TIMER_PRIORITY
+            })
+        );
+
+        // Reading the status acknowledges the interrupt
+        assert_ne!(timer.read_status() & TSR_EXPIRED, 0);
+        assert_eq!(timer.requested_interrupt(), None);
+    }
+
+    #[test]
+    fn test_disabled_timer_does_not_count() {
+        let mut timer = Timer::new();
+        timer.write_interval(1);
+        timer.count();
+        assert_eq!(timer.status & TSR_EXPIRED, 0);
+    }
+}
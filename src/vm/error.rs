@@ -0,0 +1,120 @@
+// Module for the errors the VM can stop with
+
+use super::interrupts::{
+    ACCESS_VIOLATION_VECTOR, ILLEGAL_OPCODE_VECTOR, PRIVILEGE_VIOLATION_VECTOR,
+};
+use super::Limit;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum VmError {
+    /// The instruction at `pc` uses the reserved opcode.
+    IllegalOpcode { pc: u16, instruction: u16 },
+    /// The TRAP at `pc` uses a vector with no trap routine.
+    UnknownTrap { pc: u16, vector: u16 },
+    /// The instruction at `pc` requires supervisor privilege (RTI in user mode).
+    PrivilegeViolation { pc: u16, instruction: u16 },
+    /// The instruction at `pc` accessed system space or a device register in user mode.
+    AccessViolation { pc: u16, address: u16 },
+    /// A limit set with `VM::set_limits` was exceeded; `pc` is where the program stopped.
+    LimitExceeded { limit: Limit, pc: u16 },
+    /// Reading from or writing to the console failed.
+    Io(io::Error),
+    /// An image file could not be loaded; `offset` is the byte offset in the file.
+    ImageLoad {
+        path: String,
+        offset: usize,
+        source: io::Error,
+    },
+    /// A symbol file could not be loaded.
+    SymbolLoad { path: String, source: io::Error },
+    /// A snapshot file could not be loaded.
+    SnapshotLoad { path: String, source: io::Error },
+}
+
+impl VmError {
+    /// Address of the faulting instruction, for errors raised while executing.
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            Self::IllegalOpcode { pc, .. }
+            | Self::UnknownTrap { pc, .. }
+            | Self::PrivilegeViolation { pc, .. }
+            | Self::AccessViolation { pc, .. }
+            | Self::LimitExceeded { pc, .. } => Some(*pc),
+            Self::Io(_)
+            | Self::ImageLoad { .. }
+            | Self::SymbolLoad { .. }
+            | Self::SnapshotLoad { .. } => None,
+        }
+    }
+
+    /// The interrupt vector table entry of the exception this error corresponds to, if any.
+    pub fn exception_vector(&self) -> Option<u8> {
+        match self {
+            Self::PrivilegeViolation { .. } => Some(PRIVILEGE_VIOLATION_VECTOR),
+            Self::IllegalOpcode { .. } => Some(ILLEGAL_OPCODE_VECTOR),
+            Self::AccessViolation { .. } => Some(ACCESS_VIOLATION_VECTOR),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalOpcode { pc, instruction } => {
+                write!(f, "illegal opcode x{:04X} at x{:04X}", instruction, pc)
+            }
+            Self::UnknownTrap { pc, vector } => {
+                write!(f, "unknown trap vector x{:02X} at x{:04X}", vector, pc)
+            }
+            Self::PrivilegeViolation { pc, instruction } => write!(
+                f,
+                "privilege mode violation by x{:04X} at x{:04X}",
+                instruction, pc
+            ),
+            Self::AccessViolation { pc, address } => write!(
+                f,
+                "access control violation on x{:04X} at x{:04X}",
+                address, pc
+            ),
+            Self::LimitExceeded { limit, pc } => write!(f, "{} exceeded at x{:04X}", limit, pc),
+            Self::Io(e) => write!(f, "console I/O failed: {}", e),
+            Self::ImageLoad {
+                path,
+                offset,
+                source,
+            } => write!(
+                f,
+                "failed to load image: {}: at byte {}: {}",
+                path, offset, source
+            ),
+            Self::SymbolLoad { path, source } => {
+                write!(f, "failed to load symbols: {}: {}", path, source)
+            }
+            Self::SnapshotLoad { path, source } => {
+                write!(f, "failed to load snapshot: {}: {}", path, source)
+            }
+        }
+    }
+}
+
+impl Error for VmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e)
+            | Self::ImageLoad { source: e, .. }
+            | Self::SymbolLoad { source: e, .. }
+            | Self::SnapshotLoad { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VmError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
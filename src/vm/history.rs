@@ -0,0 +1,264 @@
+// Module for the execution history: undo records of the last instructions, to step backwards
+
+use super::{MemoryWrite, Privilege, StepResult, VmError, VM};
+use std::collections::VecDeque;
+
+/* the state an instruction may change without it showing in its memory writes */
+pub(crate) struct Checkpoint {
+    registers: [u16; 10],
+    privilege: Privilege,
+    priority: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+    running: bool,
+    instructions: u64,
+    devices: Vec<Vec<u16>>,
+}
+
+/* what it takes to undo one instruction */
+struct Undo {
+    /* the registers it changed, with their old values */
+    registers: Vec<(usize, u16)>,
+    privilege: Privilege,
+    priority: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+    running: bool,
+    instructions: u64,
+    memory_writes: Vec<MemoryWrite>,
+    /* the device states before it, if it changed any */
+    devices: Option<Vec<Vec<u16>>>,
+}
+
+/// The undo records of the last `limit` instructions.
+#[derive(Default)]
+pub(crate) struct History {
+    limit: usize,
+    undo: VecDeque<Undo>,
+}
+
+impl History {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+    }
+}
+
+impl VM {
+    /// Keeps undo records of the last `limit` instructions executed, so `step_back` can
+    /// undo them. 0, the default, keeps none.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.limit = limit;
+        while self.history.undo.len() > limit {
+            self.history.undo.pop_front();
+        }
+    }
+
+    pub fn history_limit(&self) -> usize {
+        self.history.limit
+    }
+
+    /// How many instructions `step_back` can undo.
+    pub fn history_len(&self) -> usize {
+        self.history.undo.len()
+    }
+
+    /// Undoes the last instruction executed, restoring the registers, the PSR, memory and
+    /// the devices as they were before it, and returns the memory writes it undid.
+    /// Returns `None` once the history is exhausted.
+    ///
+    /// Console input read and output written by the instruction are not undone.
+    pub fn step_back(&mut self) -> Option<Vec<MemoryWrite>> {
+        let undo = self.history.undo.pop_back()?;
+        for write in undo.memory_writes.iter().rev() {
+            /* device registers come back with the device states */
+            if self.bus.peek(write.address).is_none() {
+                self.memory[write.address as usize] = write.old;
+            }
+        }
+        for &(index, old) in &undo.registers {
+            self.registers[index] = old;
+        }
+        self.privilege = undo.privilege;
+        self.priority = undo.priority;
+        self.saved_usp = undo.saved_usp;
+        self.saved_ssp = undo.saved_ssp;
+        self.running = undo.running;
+        self.instructions = undo.instructions;
+        if let Some(devices) = &undo.devices {
+            /* the history is cleared when devices are attached, so they still fit */
+            let _ = self.bus.restore_states(devices);
+        }
+        Some(undo.memory_writes)
+    }
+
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            registers: self.registers,
+            privilege: self.privilege,
+            priority: self.priority,
+            saved_usp: self.saved_usp,
+            saved_ssp: self.saved_ssp,
+            running: self.running,
+            instructions: self.instructions,
+            devices: self.bus.save_states(),
+        }
+    }
+
+    /// Records how to undo the instruction just stepped from `before`.
+    pub(crate) fn record_history(
+        &mut self,
+        before: Checkpoint,
+        result: &Result<StepResult, VmError>,
+    ) {
+        let memory_writes = match result {
+            Ok(result) => result.memory_writes.clone(),
+            /* every limit stops the VM before it executes anything */
+            Err(VmError::LimitExceeded { .. }) => return,
+            /* a faulting instruction may have written memory before it failed */
+            Err(_) => self.access_log.memory_writes.clone(),
+        };
+        let registers = (0..self.registers.len())
+            .filter(|&index| self.registers[index] != before.registers[index])
+            .map(|index| (index, before.registers[index]))
+            .collect();
+        let devices = (self.bus.save_states() != before.devices).then_some(before.devices);
+        if self.history.undo.len() == self.history.limit {
+            self.history.undo.pop_front();
+        }
+        self.history.undo.push_back(Undo {
+            registers,
+            privilege: before.privilege,
+            priority: before.priority,
+            saved_usp: before.saved_usp,
+            saved_ssp: before.saved_ssp,
+            running: before.running,
+            instructions: before.instructions,
+            memory_writes,
+            devices,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::{MemoryConsole, MemoryMappedRegister, Register};
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R1, R1, #-1
+        ST R1, COUNT
+        BRp LOOP
+        HALT
+COUNT   .FILL #3
+        .END";
+
+    fn load(limit: usize) -> VM {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(&assemble(PROGRAM).unwrap().image);
+        vm.set_history_limit(limit);
+        vm
+    }
+
+    #[test]
+    fn test_step_back_undoes_registers_and_memory() {
+        let mut vm = load(100);
+        vm.run_for(3).unwrap();
+        assert_eq!(vm.read_memory(0x3005), 2);
+
+        let undone = vm.step_back().unwrap();
+        assert_eq!(undone.len(), 1);
+        assert_eq!(vm.read_memory(0x3005), 3);
+        assert_eq!(vm.pc(), 0x3002);
+        assert_eq!(vm.reg(Register::R1), 2);
+        assert_eq!(vm.instruction_count(), 2);
+
+        vm.step_back().unwrap();
+        vm.step_back().unwrap();
+        assert_eq!(vm.pc(), 0x3000);
+        assert_eq!(vm.reg(Register::R1), 0);
+        assert!(vm.step_back().is_none());
+    }
+
+    #[test]
+    fn test_step_back_from_halt_runs_again() {
+        let mut vm = load(100);
+        vm.run().unwrap();
+        let halted = vm.snapshot();
+        assert!(!vm.is_running());
+
+        while vm.step_back().is_some() {}
+        assert!(vm.is_running());
+        assert_eq!(vm.instruction_count(), 0);
+        assert_eq!(vm.read_memory(0x3005), 3);
+
+        vm.run().unwrap();
+        assert_eq!(vm.snapshot(), halted);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut vm = load(4);
+        vm.run_for(10).unwrap();
+        assert_eq!(vm.history_len(), 4);
+        for _ in 0..4 {
+            vm.step_back().unwrap();
+        }
+        assert!(vm.step_back().is_none());
+        assert_eq!(vm.instruction_count(), 6);
+
+        vm.set_history_limit(0);
+        vm.step().unwrap();
+        assert_eq!(vm.history_len(), 0);
+    }
+
+    #[test]
+    fn test_step_back_across_an_output_limit() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(
+            &assemble(".ORIG x3000\nLOOP OUT\nADD R1, R1, #1\nBRnzp LOOP\n.END")
+                .unwrap()
+                .image,
+        );
+        vm.set_history_limit(100);
+        vm.set_limits(crate::vm::Limits {
+            max_output: Some(1),
+            ..Default::default()
+        });
+        assert!(matches!(
+            vm.run(),
+            Err(VmError::LimitExceeded { pc: 0x3001, .. })
+        ));
+        assert_eq!(vm.instruction_count(), 4);
+        assert_eq!(vm.reg(Register::R1), 1);
+
+        /* the OUT that went over the limit is the last instruction recorded */
+        assert_eq!(vm.history_len(), 4);
+        vm.step_back().unwrap();
+        assert_eq!(vm.pc(), 0x3000);
+        assert_eq!(vm.reg(Register::R1), 1);
+        assert_eq!(vm.instruction_count(), 3);
+    }
+
+    #[test]
+    fn test_step_back_restores_devices() {
+        let mut vm = load(100);
+        vm.mem_write(MemoryMappedRegister::Tir.into(), 10).unwrap();
+        vm.mem_write(MemoryMappedRegister::Tsr.into(), 0x0001)
+            .unwrap();
+        vm.run_for(3).unwrap();
+        let counter = vm.read_memory(MemoryMappedRegister::Tcr.into());
+
+        vm.step_back().unwrap();
+        assert_eq!(
+            vm.read_memory(MemoryMappedRegister::Tcr.into()),
+            counter + 1
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Module for the object image format: a big-endian origin address followed by
+// the big-endian words to load starting at that address
+
+use super::{VmError, MEMORY_SIZE};
+use std::fs;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectImage {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl ObjectImage {
+    pub fn new(origin: u16, words: Vec<u16>) -> Self {
+        ObjectImage { origin, words }
+    }
+
+    /// Reads an image file. Images with a trailing odd byte or running past xFFFF are
+    /// rejected rather than truncated.
+    pub fn read(path: &str) -> Result<Self, VmError> {
+        let image_error = |offset, source| VmError::ImageLoad {
+            path: path.to_string(),
+            offset,
+            source,
+        };
+        let bytes = fs::read(path).map_err(|e| image_error(0, e))?;
+        Self::parse(&bytes).map_err(|(offset, e)| image_error(offset, e))
+    }
+
+    /* on error, returns the byte offset of the problem along with it */
+    fn parse(bytes: &[u8]) -> Result<Self, (usize, io::Error)> {
+        // Read the origin address
+        if bytes.len() < 2 {
+            return Err((
+                bytes.len(),
+                io::Error::new(io::ErrorKind::UnexpectedEof, "missing origin address"),
+            ));
+        }
+        let origin = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let data = &bytes[2..];
+
+        if data.len() % 2 != 0 {
+            return Err((
+                bytes.len() - 1,
+                io::Error::new(io::ErrorKind::InvalidData, "truncated word"),
+            ));
+        }
+        let max_read = MEMORY_SIZE - origin as usize;
+        if data.len() / 2 > max_read {
+            return Err((
+                2 + max_read * 2,
+                io::Error::new(io::ErrorKind::InvalidData, "image does not fit in memory"),
+            ));
+        }
+
+        let words = data
+            .chunks_exact(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect();
+        Ok(ObjectImage { origin, words })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 + self.words.len() * 2);
+        bytes.extend_from_slice(&self.origin.to_be_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /// Address one past the last word of the image.
+    pub fn end(&self) -> usize {
+        self.origin as usize + self.words.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let image = ObjectImage::new(0x3000, vec![0x1234, 0xF025]);
+        let bytes = image.to_bytes();
+
+        assert_eq!(bytes, [0x30, 0x00, 0x12, 0x34, 0xF0, 0x25]);
+        assert_eq!(ObjectImage::parse(&bytes).unwrap(), image);
+        assert_eq!(image.end(), 0x3002);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(ObjectImage::parse(&[0x30]).unwrap_err().0, 1);
+        assert_eq!(ObjectImage::parse(&[0x30, 0x00, 0x12]).unwrap_err().0, 2);
+        assert_eq!(
+            ObjectImage::parse(&[0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00])
+                .unwrap_err()
+                .0,
+            4
+        );
+    }
+
+    #[test]
+    fn test_truncated_word_is_rejected() {
+        let (offset, error) = ObjectImage::parse(&[0x30, 0x00, 0x12, 0x34, 0x56]).unwrap_err();
+        assert_eq!(offset, 4);
+        assert_eq!(error.to_string(), "truncated word");
+    }
+
+    #[test]
+    fn test_image_past_the_end_of_memory_is_rejected() {
+        let (offset, error) =
+            ObjectImage::parse(&[0xFF, 0xFE, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03]).unwrap_err();
+        assert_eq!(offset, 6);
+        assert_eq!(error.to_string(), "image does not fit in memory");
+
+        /* the last word of memory is still fine */
+        let image = ObjectImage::parse(&[0xFF, 0xFE, 0x00, 0x01, 0x00, 0x02]).unwrap();
+        assert_eq!(image.end(), 0x10000);
+    }
+}
@@ -0,0 +1,288 @@
+// Module for recording the program's input and replaying it at the same points
+
+use super::Console;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+
+/// A byte of input and when the program got it, counted in instructions executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// When the byte was consumed, by `GETC`/`IN` or a read of KBDR.
+    pub instruction: u64,
+    /// When KBSR first showed the byte as ready; the same as `instruction` if it was
+    /// never polled for.
+    pub ready: u64,
+    pub byte: u8,
+}
+
+/// The input of a run, as written to and read from session logs:
+///
+/// ```text
+/// # instruction ready byte
+/// 1523 1520 x61
+/// 1790 1790 x0A
+/// ```
+///
+/// Replaying it makes the keyboard and the input traps see the same bytes after the same
+/// instructions, so a program that depends only on its input runs the same way again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputLog {
+    events: Vec<InputEvent>,
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: InputEvent) {
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut log = InputLog::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let event = match fields.as_slice() {
+                [instruction, ready, byte] => instruction
+                    .parse()
+                    .ok()
+                    .zip(ready.parse().ok())
+                    .zip(
+                        byte.strip_prefix(['x', 'X'])
+                            .and_then(|digits| u8::from_str_radix(digits, 16).ok()),
+                    )
+                    .filter(|&((instruction, ready), _)| ready <= instruction),
+                _ => None,
+            };
+            match event {
+                Some(((instruction, ready), byte)) => log.push(InputEvent {
+                    instruction,
+                    ready,
+                    byte,
+                }),
+                None => {
+                    return Err(format!(
+                    "line {}: expected an instruction count, a ready count and a byte, found '{}'",
+                    index + 1,
+                    line
+                ))
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_log_string(&self) -> String {
+        let mut text = "# instruction ready byte\n".to_string();
+        for event in &self.events {
+            text.push_str(&format!(
+                "{} {} x{:02X}\n",
+                event.instruction, event.ready, event.byte
+            ));
+        }
+        text
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_log_string())
+    }
+}
+
+/// Records the input read from a console and replays logged input in its place.
+#[derive(Debug, Default)]
+pub(crate) struct InputSession {
+    /* the VM's instruction count, kept up to date by the VM */
+    pub(crate) instructions: u64,
+    pub(crate) recording: Option<InputLog>,
+    pub(crate) replay: VecDeque<InputEvent>,
+    /* when the byte waiting to be read was first reported ready */
+    ready_at: Option<u64>,
+    /* the last instruction count for which no input was reported */
+    unready_at: Option<u64>,
+}
+
+impl InputSession {
+    pub(crate) fn key_available(&mut self, console: &mut dyn Console) -> io::Result<bool> {
+        if let Some(event) = self.replay.front() {
+            return Ok(event.ready <= self.instructions);
+        }
+        if self.recording.is_none() {
+            return console.key_available();
+        }
+        if self.ready_at.is_some() {
+            return Ok(true);
+        }
+        /* a key arriving halfway through an instruction waits for the next one, so the
+        whole instruction sees the same answer, in the recording and in the replay */
+        if self.unready_at != Some(self.instructions) && console.key_available()? {
+            self.ready_at = Some(self.instructions);
+            return Ok(true);
+        }
+        self.unready_at = Some(self.instructions);
+        Ok(false)
+    }
+
+    pub(crate) fn input_ended(&mut self, console: &mut dyn Console) -> io::Result<bool> {
+        if !self.replay.is_empty() || self.ready_at.is_some() {
+            return Ok(false);
+        }
+        console.input_ended()
+    }
+
+    pub(crate) fn read_byte(&mut self, console: &mut dyn Console) -> io::Result<u8> {
+        let (byte, ready) = match self.replay.pop_front() {
+            Some(event) if event.instruction != self.instructions => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "replay diverged: input recorded at instruction {} read at instruction {}",
+                        event.instruction, self.instructions
+                    ),
+                ));
+            }
+            Some(event) => (event.byte, event.ready),
+            /* once the replay runs out, input comes from the console again */
+            None => {
+                let byte = console.read_byte()?;
+                (byte, self.ready_at.unwrap_or(self.instructions))
+            }
+        };
+        self.ready_at = None;
+        if let Some(log) = &mut self.recording {
+            log.push(InputEvent {
+                instruction: self.instructions,
+                ready,
+                byte,
+            });
+        }
+        Ok(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::{MemoryConsole, Register, VmError, VM};
+
+    /* counts the polls of KBSR in R2 while echoing keys, until a newline */
+    const PROGRAM: &str = "
+        .ORIG x3000
+POLL    ADD R2, R2, #1
+        LDI R1, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+        OUT
+        ADD R1, R0, #-10
+        BRnp POLL
+        GETC
+        OUT
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END";
+
+    fn load(console: MemoryConsole) -> VM {
+        let mut vm = VM::with_console(console);
+        vm.load_object(&assemble(PROGRAM).unwrap().image);
+        vm
+    }
+
+    #[test]
+    fn test_log_round_trip() {
+        let mut log = InputLog::new();
+        log.push(InputEvent {
+            instruction: 1523,
+            ready: 1520,
+            byte: b'a',
+        });
+        log.push(InputEvent {
+            instruction: 1790,
+            ready: 1790,
+            byte: b'\n',
+        });
+
+        let text = log.to_log_string();
+        assert_eq!(
+            text,
+            "# instruction ready byte\n1523 1520 x61\n1790 1790 x0A\n"
+        );
+        assert_eq!(InputLog::parse(&text).unwrap(), log);
+    }
+
+    #[test]
+    fn test_replay_matches_recording() {
+        let console = MemoryConsole::new();
+        let mut vm = load(console.clone());
+        vm.record_input();
+        vm.run_for(50).unwrap();
+        console.push_input(b"a");
+        vm.run_for(100).unwrap();
+        console.push_input(b"\n!");
+        vm.run().unwrap();
+        let log = vm.recorded_input().unwrap().clone();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.events()[0].byte, b'a');
+        assert!(log.events()[0].ready < log.events()[0].instruction);
+
+        let replay_console = MemoryConsole::new();
+        let mut replay = load(replay_console.clone());
+        replay.replay_input(&log);
+        replay.run().unwrap();
+        assert!(console.output_string().starts_with("a\n!"));
+        assert_eq!(replay_console.output_string(), console.output_string());
+        assert_eq!(replay.reg(Register::R2), vm.reg(Register::R2));
+        assert_eq!(replay.instruction_count(), vm.instruction_count());
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let mut log = InputLog::new();
+        log.push(InputEvent {
+            instruction: 100,
+            ready: 0,
+            byte: b'a',
+        });
+        let mut vm = load(MemoryConsole::new());
+        vm.replay_input(&log);
+        match vm.run() {
+            Err(VmError::Io(e)) => assert_eq!(
+                e.to_string(),
+                "replay diverged: input recorded at instruction 100 read at instruction 3"
+            ),
+            other => panic!("expected the replay to diverge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            InputLog::parse("12 10 x61\n12 15 x62\n").unwrap_err(),
+            "line 2: expected an instruction count, a ready count and a byte, found '12 15 x62'"
+        );
+        assert!(InputLog::parse("12 10 61\n").is_err());
+    }
+}
@@ -0,0 +1,44 @@
+// Module for interrupts and the interrupt vector table
+
+/// Start of the interrupt vector table, x0100-x01FF.
+///
+/// Entry `vector` holds the address of the service routine for that vector.
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+pub const PRIVILEGE_VIOLATION_VECTOR: u8 = 0x00;
+pub const ILLEGAL_OPCODE_VECTOR: u8 = 0x01;
+pub const ACCESS_VIOLATION_VECTOR: u8 = 0x02;
+
+pub const KEYBOARD_VECTOR: u8 = 0x80;
+pub const KEYBOARD_PRIORITY: u16 = 4;
+
+/* user mode may only access x3000-xFDFF */
+pub(crate) const USER_SPACE_START: u16 = 0x3000;
+pub(crate) const DEVICE_SPACE_START: u16 = 0xFE00;
+
+/// What happens when an instruction raises an exception
+/// (privilege mode violation, illegal opcode or access control violation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExceptionMode {
+    /// Stop with a `VmError`, for programs running without an OS.
+    #[default]
+    Abort,
+    /// Vector through the interrupt vector table into the OS's handler.
+    Vector,
+}
+
+/// An interrupt requested by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    /// Index into the interrupt vector table.
+    pub vector: u8,
+    /// Priority level, 0-7. The interrupt is only taken above the current PSR priority.
+    pub priority: u16,
+}
+
+impl Interrupt {
+    /// The address of the entry for `vector` in the interrupt vector table.
+    pub fn table_entry(vector: u8) -> u16 {
+        INTERRUPT_VECTOR_TABLE + vector as u16
+    }
+}
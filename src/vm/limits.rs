@@ -0,0 +1,112 @@
+// Module for the limits on what a program may do, for running untrusted programs
+
+use super::input_log::InputSession;
+use super::Console;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Limits on a run. `None` means unlimited.
+///
+/// Exceeding a limit stops the VM with `VmError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Instructions executed since the VM was created.
+    pub max_instructions: Option<u64>,
+    /// Wall-clock time since the first instruction executed with this limit set.
+    pub max_time: Option<Duration>,
+    /// Bytes written to the console. Output past the limit is dropped.
+    pub max_output: Option<u64>,
+}
+
+/// The limit that stopped the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions(u64),
+    Time(Duration),
+    Output(u64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Instructions(max) => write!(f, "instruction limit of {}", max),
+            Self::Time(max) => write!(f, "time limit of {:?}", max),
+            Self::Output(max) => write!(f, "output limit of {} bytes", max),
+        }
+    }
+}
+
+/// The VM's console, counting the bytes written to it and dropping those past the
+/// output limit. Input goes through the session recording and replaying it.
+pub(crate) struct MeteredConsole {
+    pub(crate) inner: Box<dyn Console>,
+    pub(crate) written: u64,
+    pub(crate) max_output: Option<u64>,
+    pub(crate) input: InputSession,
+}
+
+impl MeteredConsole {
+    pub(crate) fn new(inner: Box<dyn Console>) -> Self {
+        MeteredConsole {
+            inner,
+            written: 0,
+            max_output: None,
+            input: InputSession::default(),
+        }
+    }
+
+    pub(crate) fn exceeded(&self) -> bool {
+        self.max_output.is_some_and(|max| self.written > max)
+    }
+}
+
+impl Console for MeteredConsole {
+    fn key_available(&mut self) -> io::Result<bool> {
+        self.input.key_available(self.inner.as_mut())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.input.read_byte(self.inner.as_mut())
+    }
+
+    fn input_ended(&mut self) -> io::Result<bool> {
+        self.input.input_ended(self.inner.as_mut())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.written += 1;
+        if self.exceeded() {
+            return Ok(());
+        }
+        self.inner.write_byte(byte)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn suspend(&mut self) -> io::Result<()> {
+        self.inner.suspend()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::MemoryConsole;
+
+    #[test]
+    fn test_metered_console_drops_output_past_the_limit() {
+        let handle = MemoryConsole::new();
+        let mut console = MeteredConsole::new(Box::new(handle.clone()));
+        console.max_output = Some(3);
+
+        console.write_bytes(b"abc").unwrap();
+        assert!(!console.exceeded());
+        console.write_bytes(b"de").unwrap();
+        assert!(console.exceeded());
+        assert_eq!(console.written, 5);
+        assert_eq!(handle.output_string(), "abc");
+    }
+}
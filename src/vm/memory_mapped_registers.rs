@@ -1,9 +1,16 @@
 // Module for the memory mapped registers of the LC3
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryMappedRegister {
     Kbsr = 0xFE00,  /* keyboard status */
     Kbddr = 0xFE02, /* keyboard data */
+    Dsr = 0xFE04,   /* display status */
+    Ddr = 0xFE06,   /* display data */
+    Tsr = 0xFE08,   /* timer status */
+    Tir = 0xFE0A,   /* timer interval */
+    Tcr = 0xFE0C,   /* timer counter */
+    Tvr = 0xFE0E,   /* timer interrupt vector and priority */
+    Mcr = 0xFFFE,   /* machine control */
 }
 
 impl From<MemoryMappedRegister> for u16 {
@@ -19,6 +26,13 @@ impl TryFrom<u16> for MemoryMappedRegister {
         Ok(match value {
             0xFE00 => Self::Kbsr,
             0xFE02 => Self::Kbddr,
+            0xFE04 => Self::Dsr,
+            0xFE06 => Self::Ddr,
+            0xFE08 => Self::Tsr,
+            0xFE0A => Self::Tir,
+            0xFE0C => Self::Tcr,
+            0xFE0E => Self::Tvr,
+            0xFFFE => Self::Mcr,
             _ => return Err("invalid memory mapped register"),
         })
     }
@@ -1,60 +1,332 @@
 mod condition_flags;
+mod console;
+mod devices;
+mod error;
+mod history;
+mod image;
+mod input_log;
+mod interrupts;
+mod limits;
 mod memory_mapped_registers;
 mod opcodes;
+mod psr;
 mod registers;
+mod snapshot;
+mod step;
+mod symbols;
 mod trap_codes;
-
-use crate::utils::terminal;
-use condition_flags::*;
-use libc::c_int;
-use memory_mapped_registers::MemoryMappedRegister;
-use opcodes::OpCode;
-use registers::*;
-use std::io::{self, Read, Write};
-use trap_codes::TrapCode;
-
-extern "C" {
-    fn getchar() -> c_int;
-}
-
-pub fn get_char() -> i32 {
-    unsafe { getchar() }
-}
-
-const MEMORY_SIZE: usize = 65536; /* 65536 locations */
+mod watch;
+
+pub use condition_flags::ConditionFlag;
+pub use console::{Console, MemoryConsole, StreamConsole, TerminalConsole};
+pub use devices::{
+    Device, DeviceContext, Display, Keyboard, MachineControl, Timer, DEVICE_PAGE_START,
+    TIMER_PRIORITY, TIMER_VECTOR,
+};
+pub use error::VmError;
+pub use image::ObjectImage;
+pub use input_log::{InputEvent, InputLog};
+pub use interrupts::{
+    ExceptionMode, Interrupt, ACCESS_VIOLATION_VECTOR, ILLEGAL_OPCODE_VECTOR,
+    INTERRUPT_VECTOR_TABLE, KEYBOARD_PRIORITY, KEYBOARD_VECTOR, PRIVILEGE_VIOLATION_VECTOR,
+};
+pub use limits::{Limit, Limits};
+pub use memory_mapped_registers::MemoryMappedRegister;
+pub use opcodes::OpCode;
+pub use psr::{Privilege, INITIAL_SSP};
+pub use registers::Register;
+pub use step::{MemoryRead, MemoryWrite, RegisterWrite, StepResult, StopReason};
+pub use symbols::SymbolTable;
+pub use trap_codes::{TrapCode, TrapMode};
+pub use watch::{WatchHit, WatchKind, Watchpoint};
+
+use devices::Bus;
+use history::History;
+use limits::MeteredConsole;
+use std::path::Path;
+use std::time::Instant;
+use step::AccessLog;
+
+pub const MEMORY_SIZE: usize = 65536; /* 65536 locations */
+
+/* instructions executed between two looks at the clock under a time limit */
+const TIME_CHECK_INTERVAL: u64 = 1024;
 
 /* 0x3000 is the default */
-const PC_START: u16 = 0x3000;
+pub const PC_START: u16 = 0x3000;
 
-pub(crate) struct VM {
+pub struct VM {
     memory: [u16; MEMORY_SIZE],
     registers: [u16; 10],
     running: bool,
+    /* the PSR is kept split up, its condition codes are the COND register */
+    privilege: Privilege,
+    priority: u16,
+    /* the stack pointer of the mode not running, R6 holds the other one */
+    saved_usp: u16,
+    saved_ssp: u16,
+    exception_mode: ExceptionMode,
+    access_control: bool,
+    trap_mode: TrapMode,
+    bus: Bus,
+    access_log: AccessLog,
+    console: MeteredConsole,
+    limits: Limits,
+    instructions: u64,
+    /* when the time limit runs out, set by the first step under the limit */
+    deadline: Option<Instant>,
+    symbols: SymbolTable,
+    watchpoints: Vec<Watchpoint>,
+    history: History,
 }
 
 impl VM {
     pub fn new() -> Self {
+        Self::with_console(TerminalConsole::new())
+    }
+
+    /// Creates a VM doing its character I/O through `console`.
+    pub fn with_console(console: impl Console + 'static) -> Self {
         let mut vm = VM {
             memory: [0; MEMORY_SIZE],
             registers: [0; 10],
             running: true,
+            privilege: Privilege::User,
+            priority: 0,
+            saved_usp: 0,
+            saved_ssp: INITIAL_SSP,
+            exception_mode: ExceptionMode::Abort,
+            access_control: false,
+            trap_mode: TrapMode::Native,
+            bus: Bus::new(),
+            access_log: AccessLog::default(),
+            console: MeteredConsole::new(Box::new(console)),
+            limits: Limits::default(),
+            instructions: 0,
+            deadline: None,
+            symbols: SymbolTable::new(),
+            watchpoints: Vec::new(),
+            history: History::default(),
         };
         /* since exactly one condition flag should be set at any given time, set the Z flag */
         vm.registers[usize::from(Register::Cond)] = ConditionFlag::Zro.into();
         /* set the PC to starting position */
         vm.registers[usize::from(Register::PC)] = PC_START;
+        vm.add_device(Keyboard::new());
+        vm.add_device(Display::new());
+        vm.add_device(Timer::new());
+        vm.add_device(MachineControl::new());
         vm
     }
 
-    fn decode(instr: u16) -> OpCode {
-        OpCode::try_from(instr >> 12).unwrap()
+    /// Returns the value held in `reg`.
+    ///
+    /// `Register::Count` is not a real register and panics.
+    pub fn reg(&self, reg: Register) -> u16 {
+        self.registers[usize::from(reg)]
+    }
+
+    /// Overwrites the value held in `reg`.
+    ///
+    /// `Register::Count` is not a real register and panics.
+    pub fn set_reg(&mut self, reg: Register, value: u16) {
+        self.registers[usize::from(reg)] = value;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.reg(Register::PC)
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.set_reg(Register::PC, value);
+    }
+
+    /// Returns the condition flag currently set, if the COND register holds a valid one.
+    pub fn cond(&self) -> Option<ConditionFlag> {
+        ConditionFlag::try_from(self.reg(Register::Cond)).ok()
+    }
+
+    /// The Processor Status Register: privilege (bit 15), priority (bits 10-8)
+    /// and condition codes (bits 2-0).
+    pub fn psr(&self) -> u16 {
+        let privilege = match self.privilege {
+            Privilege::User => psr::PRIVILEGE_BIT,
+            Privilege::Supervisor => 0,
+        };
+        privilege
+            | (self.priority << psr::PRIORITY_SHIFT)
+            | (self.reg(Register::Cond) & psr::CONDITION_MASK)
+    }
+
+    /// Overwrites the PSR. R6 is left alone, even if the privilege changes.
+    pub fn set_psr(&mut self, value: u16) {
+        self.privilege = Privilege::from_psr(value);
+        self.priority = (value >> psr::PRIORITY_SHIFT) & psr::PRIORITY_MASK;
+        self.set_reg(Register::Cond, value & psr::CONDITION_MASK);
+    }
+
+    pub fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// The user stack pointer saved while running in supervisor mode.
+    pub fn saved_usp(&self) -> u16 {
+        self.saved_usp
+    }
+
+    pub fn set_saved_usp(&mut self, value: u16) {
+        self.saved_usp = value;
+    }
+
+    /// The supervisor stack pointer saved while running in user mode.
+    pub fn saved_ssp(&self) -> u16 {
+        self.saved_ssp
+    }
+
+    pub fn set_saved_ssp(&mut self, value: u16) {
+        self.saved_ssp = value;
+    }
+
+    pub fn exception_mode(&self) -> ExceptionMode {
+        self.exception_mode
+    }
+
+    /// Chooses between stopping with an error and running the OS's exception handlers.
+    pub fn set_exception_mode(&mut self, mode: ExceptionMode) {
+        self.exception_mode = mode;
+    }
+
+    pub fn access_control(&self) -> bool {
+        self.access_control
+    }
+
+    /// Makes user mode accesses to system space (x0000-x2FFF) and device registers
+    /// (xFE00-xFFFF) raise an access control violation. Off by default.
+    ///
+    /// This includes the trap vector table, so with `TrapMode::Table` only supervisor
+    /// mode code can use TRAP.
+    pub fn set_access_control(&mut self, enabled: bool) {
+        self.access_control = enabled;
+    }
+
+    pub fn trap_mode(&self) -> TrapMode {
+        self.trap_mode
+    }
+
+    pub fn set_trap_mode(&mut self, mode: TrapMode) {
+        self.trap_mode = mode;
+    }
+
+    /// Reads a memory location without triggering any memory mapped device.
+    ///
+    /// Device registers show their current value, as the device's `peek` reports it.
+    pub fn read_memory(&self, address: u16) -> u16 {
+        self.bus
+            .peek(address)
+            .unwrap_or(self.memory[address as usize])
+    }
+
+    /// Writes a memory location without triggering any memory mapped device.
+    pub fn write_memory(&mut self, address: u16, value: u16) {
+        self.memory[address as usize] = value;
+    }
+
+    /// The whole 64K word address space.
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
     }
 
-    fn fetch(&mut self) -> u16 {
-        self.mem_read(self.registers[usize::from(Register::PC)])
+    /// Plugs a device into the device page, xFE00-xFFFF.
+    ///
+    /// The keyboard, display, timer and MCR are plugged in from the start; a device
+    /// claiming one of their addresses takes it over, and one claiming all of them
+    /// replaces the device altogether.
+    /// Panics if the device claims an address outside the device page.
+    pub fn add_device(&mut self, device: impl Device + 'static) {
+        self.bus.attach(Box::new(device));
+        self.history.clear();
     }
 
-    fn execute(&mut self, op: OpCode, instr: u16) {
+    pub fn set_console(&mut self, console: impl Console + 'static) {
+        self.console.inner = Box::new(console);
+    }
+
+    pub fn console_mut(&mut self) -> &mut dyn Console {
+        self.console.inner.as_mut()
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Sets the limits on the run; exceeding one stops the VM with `VmError::LimitExceeded`.
+    ///
+    /// The time limit starts counting from the next instruction.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.console.max_output = limits.max_output;
+        self.deadline = None;
+    }
+
+    /// Starts recording the input the program reads, with when it reads it.
+    pub fn record_input(&mut self) {
+        self.console.input.recording = Some(InputLog::new());
+    }
+
+    /// The input recorded since `record_input`.
+    pub fn recorded_input(&self) -> Option<&InputLog> {
+        self.console.input.recording.as_ref()
+    }
+
+    /// Feeds the program the input of `log` at the points it was recorded, instead of
+    /// reading from the console until the log runs out. Input read at any other point
+    /// fails, as the run no longer follows the recording.
+    pub fn replay_input(&mut self, log: &InputLog) {
+        self.console.input.replay = log.events().iter().copied().collect();
+    }
+
+    /// The number of instructions executed so far.
+    pub fn instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn set_running(&mut self, running: bool) {
+        self.running = running;
+    }
+
+    fn decode(pc: u16, instr: u16) -> Result<OpCode, VmError> {
+        OpCode::try_from(instr >> 12).map_err(|_| VmError::IllegalOpcode {
+            pc,
+            instruction: instr,
+        })
+    }
+
+    fn fetch(&mut self) -> Result<u16, VmError> {
+        /* the instruction fetch is not part of what the instruction accesses */
+        let reads = self.access_log.memory_reads.len();
+        let watch_hits = self.access_log.watch_hits.len();
+        let pc = self.registers[usize::from(Register::PC)];
+        self.check_access(pc, pc)?;
+        let instr = self.mem_read(pc)?;
+        self.access_log.memory_reads.truncate(reads);
+        self.access_log.watch_hits.truncate(watch_hits);
+        Ok(instr)
+    }
+
+    /* address of the instruction being executed, the PC has already been incremented */
+    fn instruction_address(&self) -> u16 {
+        self.pc().wrapping_sub(1)
+    }
+
+    fn execute(&mut self, op: OpCode, instr: u16) -> Result<(), VmError> {
         match op {
             OpCode::Add => self.add(instr),
             OpCode::And => self.and(instr),
@@ -62,32 +334,195 @@ impl VM {
             OpCode::Br => self.br(instr),
             OpCode::Jmp => self.jmp(instr),
             OpCode::Jsr => self.jsr(instr),
-            OpCode::Ld => self.ld(instr),
-            OpCode::Ldi => self.ldi(instr),
-            OpCode::Ldr => self.ldr(instr),
+            OpCode::Ld => self.ld(instr)?,
+            OpCode::Ldi => self.ldi(instr)?,
+            OpCode::Ldr => self.ldr(instr)?,
             OpCode::Lea => self.lea(instr),
-            OpCode::St => self.st(instr),
-            OpCode::Sti => self.sti(instr),
-            OpCode::Str => self.str(instr),
-            OpCode::Trap => self.trap(instr),
-            OpCode::Rti | OpCode::Res => self.abort(),
+            OpCode::St => self.st(instr)?,
+            OpCode::Sti => self.sti(instr)?,
+            OpCode::Str => self.str(instr)?,
+            OpCode::Trap => self.trap(instr)?,
+            OpCode::Rti => self.rti(instr)?,
+            OpCode::Res => {
+                return Err(VmError::IllegalOpcode {
+                    pc: self.instruction_address(),
+                    instruction: instr,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes exactly one instruction and reports what it did.
+    ///
+    /// On error the machine stops running.
+    pub fn step(&mut self) -> Result<StepResult, VmError> {
+        let before = self.history.is_enabled().then(|| self.checkpoint());
+        let result = self.try_step();
+        if result.is_err() {
+            self.running = false;
+        }
+        if let Some(before) = before {
+            self.record_history(before, &result);
         }
+        result
+    }
+
+    fn try_step(&mut self) -> Result<StepResult, VmError> {
+        self.access_log.clear();
+        self.check_limits()?;
+        self.console.input.instructions = self.instructions;
+        let mut interrupt = self.take_interrupt()?;
+        let instr: u16 = match self.fetch() {
+            Ok(instr) => instr,
+            Err(e) => {
+                /* an exception raised by the fetch is taken before the instruction, like an interrupt */
+                interrupt = Some(self.raise_exception(e)?);
+                self.fetch()?
+            }
+        };
+        let pc_before = self.pc();
+        self.registers[usize::from(Register::PC)] = pc_before.wrapping_add(1);
+        let op = Self::decode(pc_before, instr)?;
+        let exception = match self.execute(op, instr) {
+            Ok(()) => None,
+            Err(e) => Some(self.raise_exception(e)?),
+        };
+        let mut context = DeviceContext {
+            console: &mut self.console,
+            running: &mut self.running,
+        };
+        self.bus.tick(&mut context)?;
+        self.instructions += 1;
+        Ok(StepResult {
+            pc_before,
+            pc_after: self.pc(),
+            instruction: instr,
+            opcode: op,
+            register_writes: std::mem::take(&mut self.access_log.register_writes),
+            memory_reads: std::mem::take(&mut self.access_log.memory_reads),
+            memory_writes: std::mem::take(&mut self.access_log.memory_writes),
+            watch_hits: std::mem::take(&mut self.access_log.watch_hits),
+            trap: (op == OpCode::Trap).then_some(instr & 0xFF),
+            interrupt,
+            exception,
+            halted: !self.running,
+        })
     }
 
-    pub fn run(&mut self) {
+    /// Runs until the machine halts or a watchpoint triggers.
+    pub fn run(&mut self) -> Result<StopReason, VmError> {
         while self.running {
-            let instr: u16 = self.fetch();
-            self.registers[usize::from(Register::PC)] += 1;
-            let op = Self::decode(instr);
-            self.execute(op, instr);
+            if !self.step()?.watch_hits.is_empty() {
+                return Ok(StopReason::Watchpoint);
+            }
+        }
+        Ok(StopReason::Halted)
+    }
+
+    /// Runs at most `steps` instructions, stopping early if a watchpoint triggers.
+    pub fn run_for(&mut self, steps: usize) -> Result<StopReason, VmError> {
+        for _ in 0..steps {
+            if !self.running {
+                return Ok(StopReason::Halted);
+            }
+            if !self.step()?.watch_hits.is_empty() {
+                return Ok(StopReason::Watchpoint);
+            }
+        }
+        if self.running {
+            Ok(StopReason::StepLimit)
+        } else {
+            Ok(StopReason::Halted)
         }
     }
 
-    pub fn load_image(&mut self, path: &str) -> io::Result<()> {
-        self.read_image(path)
+    /// Runs until the machine halts, `predicate` returns true for an executed instruction
+    /// or a watchpoint triggers.
+    ///
+    /// The predicate also sees the instruction that triggered a watchpoint.
+    pub fn run_until<F>(&mut self, mut predicate: F) -> Result<StopReason, VmError>
+    where
+        F: FnMut(&VM, &StepResult) -> bool,
+    {
+        while self.running {
+            let result = self.step()?;
+            if predicate(self, &result) {
+                return Ok(StopReason::Predicate);
+            }
+            if !result.watch_hits.is_empty() {
+                return Ok(StopReason::Watchpoint);
+            }
+        }
+        Ok(StopReason::Halted)
     }
 
-    fn sign_extend(x: u16, bit_count: u16) -> u16 {
+    /// Loads an image file, along with the symbols of the `.sym` file next to it if there is one.
+    /// Returns the origin of the image.
+    pub fn load_image(&mut self, path: &str) -> Result<u16, VmError> {
+        let origin = self.read_image(path)?;
+        let sym_path = Path::new(path).with_extension("sym");
+        if sym_path.is_file() {
+            self.load_symbols(&sym_path.to_string_lossy())?;
+        }
+        Ok(origin)
+    }
+
+    /// Adds the symbols of a `.sym` file to the symbol table.
+    pub fn load_symbols(&mut self, path: &str) -> Result<(), VmError> {
+        let symbols = SymbolTable::read(path).map_err(|e| VmError::SymbolLoad {
+            path: path.to_string(),
+            source: e,
+        })?;
+        self.symbols.extend(&symbols);
+        Ok(())
+    }
+
+    /// Adds a watchpoint, checked on every memory access made by an instruction.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoints covering `address`, returning how many were removed.
+    pub fn remove_watchpoints_at(&mut self, address: u16) -> usize {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| !w.contains(address));
+        count - self.watchpoints.len()
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    /// Loads an OS image and lets it handle traps and exceptions: TRAP goes through
+    /// the trap vector table and exceptions through the interrupt vector table.
+    pub fn load_os(&mut self, image: &ObjectImage) {
+        self.load_object(image);
+        self.trap_mode = TrapMode::Table;
+        self.exception_mode = ExceptionMode::Vector;
+    }
+
+    /// Copies `image` into memory at its origin. Words that would go past xFFFF are
+    /// dropped; `ObjectImage::read` rejects such images.
+    pub fn load_object(&mut self, image: &ObjectImage) {
+        let origin = image.origin as usize;
+        let len = image.words.len().min(MEMORY_SIZE - origin);
+        self.memory[origin..origin + len].copy_from_slice(&image.words[..len]);
+    }
+
+    pub(crate) fn sign_extend(x: u16, bit_count: u16) -> u16 {
         // if the leftmost bit is 1, then it's negative
         if (x >> (bit_count - 1)) & 1 == 1 {
             // set the leftmost bits to 1
@@ -97,15 +532,25 @@ impl VM {
         }
     }
 
+    fn set_register(&mut self, reg_index: usize, value: u16) {
+        self.access_log.register_writes.push(RegisterWrite {
+            register: Register::try_from(reg_index).unwrap(),
+            old: self.registers[reg_index],
+            new: value,
+        });
+        self.registers[reg_index] = value;
+    }
+
     fn update_flags(&mut self, reg_index: usize) {
-        if self.registers[reg_index] == 0 {
-            self.registers[usize::from(Register::Cond)] = ConditionFlag::Zro.into();
+        let flag = if self.registers[reg_index] == 0 {
+            ConditionFlag::Zro
         } else if self.registers[reg_index] >> 15 == 1 {
             /* a 1 in the left-most bit indicates negative */
-            self.registers[usize::from(Register::Cond)] = ConditionFlag::Neg.into();
+            ConditionFlag::Neg
         } else {
-            self.registers[usize::from(Register::Cond)] = ConditionFlag::Pos.into();
-        }
+            ConditionFlag::Pos
+        };
+        self.set_register(usize::from(Register::Cond), flag.into());
     }
 
     fn add(&mut self, instr: u16) {
@@ -124,12 +569,14 @@ impl VM {
         if imm_flag != 0 {
             // immediate mode
             let imm5 = Self::sign_extend(instr & 0x1F, 5);
-            self.registers[dr as usize] = self.registers[sr1 as usize].wrapping_add(imm5);
+            self.set_register(dr as usize, self.registers[sr1 as usize].wrapping_add(imm5));
         } else {
             // register mode
             let sr2 = instr & 0x7;
-            self.registers[dr as usize] =
-                self.registers[sr1 as usize].wrapping_add(self.registers[sr2 as usize]);
+            self.set_register(
+                dr as usize,
+                self.registers[sr1 as usize].wrapping_add(self.registers[sr2 as usize]),
+            );
         }
 
         self.update_flags(dr as usize);
@@ -147,15 +594,18 @@ impl VM {
 
         if imm_flag != 0 {
             let imm5 = Self::sign_extend(instr & 0x1F, 5);
-            self.registers[dr as usize] = self.registers[sr1 as usize] & imm5;
+            self.set_register(dr as usize, self.registers[sr1 as usize] & imm5);
         } else {
             let sr2 = instr & 0x7;
-            self.registers[dr as usize] =
-                self.registers[sr1 as usize] & self.registers[sr2 as usize];
+            self.set_register(
+                dr as usize,
+                self.registers[sr1 as usize] & self.registers[sr2 as usize],
+            );
         }
+        self.update_flags(dr as usize);
     }
 
-    fn ldi(&mut self, instr: u16) {
+    fn ldi(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 | 8 7 6 | 5 4 3 2 1 0
                 1 0 1 0 |   DR    |  PCoffset9
@@ -165,9 +615,11 @@ impl VM {
         let pc_offset = Self::sign_extend(instr & 0x1FF, 9);
         /* add pc_offset to the current PC, look at that memory location to get the final address */
         let address = self.registers[usize::from(Register::PC)].wrapping_add(pc_offset);
-        let effective_address = self.mem_read(address);
-        self.registers[dr as usize] = self.mem_read(effective_address);
+        let effective_address = self.mem_read(address)?;
+        let value = self.mem_read(effective_address)?;
+        self.set_register(dr as usize, value);
         self.update_flags(dr as usize);
+        Ok(())
     }
 
     fn not(&mut self, instr: u16) {
@@ -177,7 +629,7 @@ impl VM {
         */
         let dr = (instr >> 9) & 0x7;
         let sr = (instr >> 6) & 0x7;
-        self.registers[dr as usize] = !self.registers[sr as usize];
+        self.set_register(dr as usize, !self.registers[sr as usize]);
         self.update_flags(dr as usize);
     }
 
@@ -218,7 +670,10 @@ impl VM {
         */
         // First, the incremented PC is saved in R7.
         // This is the linkage back to the calling routine.
-        self.registers[usize::from(Register::R7)] = self.registers[usize::from(Register::PC)];
+        self.set_register(
+            usize::from(Register::R7),
+            self.registers[usize::from(Register::PC)],
+        );
         let long_flag = (instr >> 11) & 0x1;
         if long_flag != 0 {
             // JSR
@@ -232,7 +687,7 @@ impl VM {
         }
     }
 
-    fn ld(&mut self, instr: u16) {
+    fn ld(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 | 8 7 6 5 4 3 2 1 0
                 0 0 1 0 |   DR    |  PCoffset9
@@ -240,11 +695,13 @@ impl VM {
         let dr = (instr >> 9) & 0x7;
         let pc_offset = Self::sign_extend(instr & 0x1FF, 9);
         let address = self.registers[usize::from(Register::PC)].wrapping_add(pc_offset);
-        self.registers[dr as usize] = self.memory[address as usize];
+        let value = self.mem_read(address)?;
+        self.set_register(dr as usize, value);
         self.update_flags(dr as usize);
+        Ok(())
     }
 
-    fn ldr(&mut self, instr: u16) {
+    fn ldr(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 | 8 7 6 | 5 4 3 2 1 0
                 0 1 1 0 |    DR   | BaseR | 6-bit offset
@@ -253,8 +710,119 @@ impl VM {
         let base_r = (instr >> 6) & 0x7;
         let offset = Self::sign_extend(instr & 0x3F, 6);
         let address = self.registers[base_r as usize].wrapping_add(offset);
-        self.registers[dr as usize] = self.memory[address as usize];
+        let value = self.mem_read(address)?;
+        self.set_register(dr as usize, value);
         self.update_flags(dr as usize);
+        Ok(())
+    }
+
+    /* stops before the next instruction once the instruction or time limit is reached */
+    fn check_limits(&mut self) -> Result<(), VmError> {
+        let pc = self.pc();
+        /* the instruction that went over the output limit has completed, stop after it */
+        if let Some(max) = self.limits.max_output.filter(|_| self.console.exceeded()) {
+            return Err(VmError::LimitExceeded {
+                limit: Limit::Output(max),
+                pc,
+            });
+        }
+        if let Some(max) = self.limits.max_instructions {
+            if self.instructions >= max {
+                return Err(VmError::LimitExceeded {
+                    limit: Limit::Instructions(max),
+                    pc,
+                });
+            }
+        }
+        if let Some(max) = self.limits.max_time {
+            let deadline = *self.deadline.get_or_insert_with(|| Instant::now() + max);
+            /* reading the clock costs more than an instruction, so only look now and then */
+            if self.instructions % TIME_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(VmError::LimitExceeded {
+                    limit: Limit::Time(max),
+                    pc,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /* the highest priority device interrupt being requested, if any */
+    fn pending_interrupt(&mut self) -> Result<Option<Interrupt>, VmError> {
+        let mut context = DeviceContext {
+            console: &mut self.console,
+            running: &mut self.running,
+        };
+        self.bus.pending_interrupt(&mut context)
+    }
+
+    /* takes a pending interrupt of higher priority than the running program */
+    fn take_interrupt(&mut self) -> Result<Option<u8>, VmError> {
+        match self.pending_interrupt()? {
+            Some(interrupt) if interrupt.priority > self.priority => {
+                self.enter_service_routine(interrupt.vector, interrupt.priority)?;
+                Ok(Some(interrupt.vector))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /* runs the OS's handler for an exception, or gives the error back when there is no OS */
+    fn raise_exception(&mut self, error: VmError) -> Result<u8, VmError> {
+        match error.exception_vector() {
+            Some(vector) if self.exception_mode == ExceptionMode::Vector => {
+                self.enter_service_routine(vector, self.priority)?;
+                Ok(vector)
+            }
+            _ => Err(error),
+        }
+    }
+
+    /* switches to supervisor mode, saves the PSR and PC on the supervisor stack
+    and jumps to the routine in the interrupt vector table */
+    fn enter_service_routine(&mut self, vector: u8, priority: u16) -> Result<(), VmError> {
+        let psr = self.psr();
+        if self.privilege == Privilege::User {
+            self.saved_usp = self.registers[usize::from(Register::R6)];
+            self.set_register(usize::from(Register::R6), self.saved_ssp);
+            self.privilege = Privilege::Supervisor;
+        }
+        self.priority = priority;
+        let sp = self.registers[usize::from(Register::R6)];
+        self.mem_write(sp.wrapping_sub(1), psr)?;
+        self.mem_write(sp.wrapping_sub(2), self.pc())?;
+        self.set_register(usize::from(Register::R6), sp.wrapping_sub(2));
+        self.registers[usize::from(Register::PC)] =
+            self.mem_read(Interrupt::table_entry(vector))?;
+        Ok(())
+    }
+
+    fn rti(&mut self, instr: u16) -> Result<(), VmError> {
+        /*
+            15 14 13 12 | 11 10 9 8 7 6 5 4 3 2 1 0
+                1 0 0 0 | 0  0  0 0 0 0 0 0 0 0 0 0
+        */
+        if self.privilege == Privilege::User {
+            return Err(VmError::PrivilegeViolation {
+                pc: self.instruction_address(),
+                instruction: instr,
+            });
+        }
+        // Pop the PC, then the PSR, off the supervisor stack.
+        let sp = self.registers[usize::from(Register::R6)];
+        let pc = self.mem_read(sp)?;
+        let psr = self.mem_read(sp.wrapping_add(1))?;
+        self.registers[usize::from(Register::PC)] = pc;
+        self.set_register(usize::from(Register::R6), sp.wrapping_add(2));
+        self.privilege = Privilege::from_psr(psr);
+        self.priority = (psr >> psr::PRIORITY_SHIFT) & psr::PRIORITY_MASK;
+        self.set_register(usize::from(Register::Cond), psr & psr::CONDITION_MASK);
+        // Returning to user mode switches back to the user stack.
+        if self.privilege == Privilege::User {
+            self.saved_ssp = self.registers[usize::from(Register::R6)];
+            self.set_register(usize::from(Register::R6), self.saved_usp);
+        }
+        Ok(())
     }
 
     fn lea(&mut self, instr: u16) {
@@ -265,11 +833,11 @@ impl VM {
         let dr = (instr >> 9) & 0x7;
         let pc_offset = Self::sign_extend(instr & 0x1FF, 9);
         let address = self.registers[usize::from(Register::PC)].wrapping_add(pc_offset);
-        self.registers[dr as usize] = address;
+        self.set_register(dr as usize, address);
         self.update_flags(dr as usize);
     }
 
-    fn st(&mut self, instr: u16) {
+    fn st(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 | 8 7 6 5 4 3 2 1 0
                 0 0 1 1 |    SR   |  PCoffset9
@@ -277,10 +845,10 @@ impl VM {
         let sr = (instr >> 9) & 0x7;
         let pc_offset = Self::sign_extend(instr & 0x1FF, 9);
         let address = self.registers[usize::from(Register::PC)].wrapping_add(pc_offset);
-        self.memory[address as usize] = self.registers[sr as usize];
+        self.mem_write(address, self.registers[sr as usize])
     }
 
-    fn sti(&mut self, instr: u16) {
+    fn sti(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 | 8 7 6 5 4 3 2 1 0
                 1 0 1 1 |    SR   |  PCoffset9
@@ -288,11 +856,11 @@ impl VM {
         let sr = (instr >> 9) & 0x7;
         let pc_offset = Self::sign_extend(instr & 0x1FF, 9);
         let address = self.registers[usize::from(Register::PC)].wrapping_add(pc_offset);
-        let effective_address = self.memory[address as usize];
-        self.memory[effective_address as usize] = self.registers[sr as usize];
+        let effective_address = self.mem_read(address)?;
+        self.mem_write(effective_address, self.registers[sr as usize])
     }
 
-    fn str(&mut self, instr: u16) {
+    fn str(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 | 8 7 6 | 5 4 3 2 1 0
                 0 1 1 1 |    SR   | BaseR | offset6
@@ -301,17 +869,31 @@ impl VM {
         let base_r = (instr >> 6) & 0x7;
         let offset = Self::sign_extend(instr & 0x3F, 6);
         let address = self.registers[base_r as usize].wrapping_add(offset);
-        self.memory[address as usize] = self.registers[sr as usize];
+        self.mem_write(address, self.registers[sr as usize])
     }
 
-    fn trap(&mut self, instr: u16) {
+    fn trap(&mut self, instr: u16) -> Result<(), VmError> {
         /*
             15 14 13 12 | 11 10 9 8 7 6 5 4 3 2 1 0
                 1 1 1 1 | 0 0 0 0 |   trapvect8
         */
-        terminal::turn_off_canonical_and_echo_modes();
         let trap_vect = instr & 0xFF;
-        match trap_vect.try_into().unwrap() {
+        if self.trap_mode == TrapMode::Table {
+            // R7 <- PC; PC <- mem[trapvect8], without leaving user mode
+            // (with access control, user mode faults on reading the table)
+            let routine = self.mem_read(trap_vect)?;
+            self.set_register(
+                usize::from(Register::R7),
+                self.registers[usize::from(Register::PC)],
+            );
+            self.registers[usize::from(Register::PC)] = routine;
+            return Ok(());
+        }
+        let trap_code = TrapCode::try_from(trap_vect).map_err(|_| VmError::UnknownTrap {
+            pc: self.instruction_address(),
+            vector: trap_vect,
+        })?;
+        match trap_code {
             TrapCode::Getc => self.trap_getc(),
             TrapCode::Out => self.trap_out(),
             TrapCode::Puts => self.trap_puts(),
@@ -319,119 +901,168 @@ impl VM {
             TrapCode::Putsp => self.trap_puts_p(),
             TrapCode::Halt => self.trap_halt(),
         }
-        terminal::restore_terminal_settings();
     }
 
-    fn abort(&mut self) {
-        println!("Bad Opcode!");
-        println!("Aborting the VM...");
-        self.running = false;
+    fn read_console(&mut self) -> Result<u8, VmError> {
+        Ok(self.console.read_byte()?)
+    }
+
+    fn write_console(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        Ok(self.console.write_bytes(bytes)?)
     }
 
-    fn trap_getc(&mut self) {
+    fn flush_console(&mut self) -> Result<(), VmError> {
+        Ok(self.console.flush()?)
+    }
+
+    fn trap_getc(&mut self) -> Result<(), VmError> {
         let register_index = usize::from(Register::R0);
-        self.registers[register_index] = get_char() as u16;
+        let c = self.read_console()?;
+        self.set_register(register_index, c as u16);
         self.update_flags(register_index);
+        Ok(())
     }
 
-    fn trap_out(&mut self) {
-        print!(
-            "{}",
-            self.registers[usize::from(Register::R0)] as u8 as char
-        );
-        io::stdout().flush().expect("Flushed.");
+    fn trap_out(&mut self) -> Result<(), VmError> {
+        let c = self.registers[usize::from(Register::R0)] as u8;
+        self.write_console(&[c])?;
+        self.flush_console()
     }
 
-    fn trap_puts(&mut self) {
+    fn trap_puts(&mut self) -> Result<(), VmError> {
         let mut address = self.registers[usize::from(Register::R0)];
-        while self.memory[address as usize] != 0x0000 {
-            print!("{}", self.memory[address as usize] as u8 as char);
-            address += 1;
+        loop {
+            let c = self.mem_read(address)?;
+            if c == 0x0000 {
+                break;
+            }
+            self.write_console(&[c as u8])?;
+            address = address.wrapping_add(1);
         }
-        io::stdout().flush().expect("Flushed.");
+        self.flush_console()
     }
 
-    fn trap_in(&mut self) {
-        print!("Enter a character: ");
-        io::stdout().flush().expect("Flushed.");
+    fn trap_in(&mut self) -> Result<(), VmError> {
+        self.write_console(b"Enter a character: ")?;
+        self.flush_console()?;
         let register_index = usize::from(Register::R0);
-        self.registers[register_index] = get_char() as u16;
+        let c = self.read_console()?;
+        self.set_register(register_index, c as u16);
         self.update_flags(register_index);
+        Ok(())
     }
 
-    fn trap_puts_p(&mut self) {
+    fn trap_puts_p(&mut self) -> Result<(), VmError> {
         /* one char per byte (two bytes per word)
         here we need to swap back to
         big endian format */
         let mut address = self.registers[usize::from(Register::R0)];
-        while self.memory[address as usize] != 0x0000 {
-            let c = self.memory[address as usize];
-            let c1 = (c & 0xFF) as u8 as char;
-            print!("{}", c1);
-            let c2 = (c >> 8) as u8 as char;
-            if c2 != '\0' {
-                print!("{}", c2);
+        loop {
+            let c = self.mem_read(address)?;
+            if c == 0x0000 {
+                break;
             }
-            address += 1;
+            let c1 = (c & 0xFF) as u8;
+            self.write_console(&[c1])?;
+            let c2 = (c >> 8) as u8;
+            if c2 != 0 {
+                self.write_console(&[c2])?;
+            }
+            address = address.wrapping_add(1);
         }
-        io::stdout().flush().expect("Flushed.");
+        self.flush_console()
     }
 
-    fn trap_halt(&mut self) {
-        println!("Halting the VM...");
+    fn trap_halt(&mut self) -> Result<(), VmError> {
+        self.write_console(b"Halting the VM...\n")?;
         self.running = false;
-        io::stdout().flush().expect("Flushed.");
+        self.flush_console()
     }
 
-    fn read_image_file(&mut self, file: &mut std::fs::File) -> std::io::Result<()> {
-        // Read the origin address
-        let mut origin_buf = [0; 2];
-        file.read_exact(&mut origin_buf)?;
-        let origin = u16::from_be_bytes(origin_buf) as usize;
-
-        // Read the file content into memory
-        let max_read = MEMORY_SIZE - origin;
-        let mut buffer = vec![0; max_read * 2];
-        let bytes_read = file.read(&mut buffer)?;
+    fn read_image(&mut self, image_path: &str) -> Result<u16, VmError> {
+        let image = ObjectImage::read(image_path)?;
+        self.load_object(&image);
+        Ok(image.origin)
+    }
 
-        // Convert and copy the data into memory
-        for i in 0..(bytes_read / 2) {
-            // let word = Self::swap16(u16::from_be_bytes([buffer[2 * i], buffer[2 * i + 1]]));
-            let word = u16::from_be_bytes([buffer[2 * i], buffer[2 * i + 1]]);
-            self.mem_write(origin + i, word);
+    /* user mode may not touch system space or the device registers when access control is on */
+    fn check_access(&self, pc: u16, address: u16) -> Result<(), VmError> {
+        let system =
+            !(interrupts::USER_SPACE_START..interrupts::DEVICE_SPACE_START).contains(&address);
+        if self.access_control && self.privilege == Privilege::User && system {
+            return Err(VmError::AccessViolation { pc, address });
         }
         Ok(())
     }
 
-    fn read_image(&mut self, image_path: &str) -> std::io::Result<()> {
-        let mut file = std::fs::File::open(image_path)?;
-        self.read_image_file(&mut file)
+    fn mem_write(&mut self, address: u16, value: u16) -> Result<(), VmError> {
+        self.check_access(self.instruction_address(), address)?;
+        let old = self.read_memory(address);
+        let mut context = DeviceContext {
+            console: &mut self.console,
+            running: &mut self.running,
+        };
+        let new = match self.bus.device_at(address) {
+            Some(device) => {
+                device.write(address, value, &mut context)?;
+                device.peek(address)
+            }
+            None => {
+                self.memory[address as usize] = value;
+                value
+            }
+        };
+        self.access_log
+            .memory_writes
+            .push(MemoryWrite { address, old, new });
+        self.check_watchpoints(address, WatchKind::Write, old, new);
+        Ok(())
     }
 
-    fn mem_write(&mut self, address: usize, value: u16) {
-        self.memory[address] = value;
+    fn check_watchpoints(&mut self, address: u16, kind: WatchKind, old: u16, new: u16) {
+        let write = kind == WatchKind::Write;
+        if self.watchpoints.iter().any(|w| w.triggers(address, write)) {
+            self.access_log.watch_hits.push(WatchHit {
+                address,
+                kind,
+                old,
+                new,
+            });
+        }
     }
 
-    fn mem_read(&mut self, address: u16) -> u16 {
-        if address == MemoryMappedRegister::Kbsr.into() {
-            let mut buffer = [0; 1];
-            std::io::stdin().read_exact(&mut buffer).unwrap();
-            if buffer[0] != 0 {
-                self.memory[usize::from(MemoryMappedRegister::Kbsr)] = 1 << 15;
-                self.memory[usize::from(MemoryMappedRegister::Kbddr)] = get_char() as u16;
-            } else {
-                self.memory[usize::from(MemoryMappedRegister::Kbsr)] = 0;
-            }
-        }
-        self.memory[address as usize]
+    fn mem_read(&mut self, address: u16) -> Result<u16, VmError> {
+        self.check_access(self.instruction_address(), address)?;
+        let mut context = DeviceContext {
+            console: &mut self.console,
+            running: &mut self.running,
+        };
+        let value = match self.bus.device_at(address) {
+            Some(device) => device.read(address, &mut context)?,
+            None => self.memory[address as usize],
+        };
+        self.access_log
+            .memory_reads
+            .push(MemoryRead { address, value });
+        self.check_watchpoints(address, WatchKind::Read, value, value);
+        Ok(value)
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
     use std::fs::File;
 
-    use io::Write;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use std::time::Duration;
 
     use super::*;
 
@@ -485,7 +1116,7 @@ mod tests {
         // Binary representation: 1010 000 000 000010
         let instr: u16 = 0b1010_0000_0000_0010;
 
-        vm.ldi(instr);
+        vm.ldi(instr).unwrap();
 
         println!("Registers after LDI: {:?}", vm.registers);
         println!("Memory after LDI: {:?}", &vm.memory[0x3000..0x3060]);
@@ -500,13 +1131,59 @@ mod tests {
         // Create an RTI instruction
         // Binary representation: 1000 0000 0000 0000
         let instr: u16 = 0b1000_0000_0000_0000;
+        vm.memory[0x3000] = instr;
 
-        vm.execute(OpCode::Rti, instr);
+        let result = vm.step();
 
         println!("Registers after RTI: {:?}", vm.registers);
+        assert!(matches!(
+            result,
+            Err(VmError::PrivilegeViolation {
+                pc: 0x3000,
+                instruction: 0x8000
+            })
+        ));
         assert!(!vm.running);
     }
 
+    #[test]
+    fn test_rti_supervisor() {
+        let mut vm = VM::new();
+        vm.memory[0x0500] = 0b1000_0000_0000_0000;
+        // Supervisor stack: return PC, then a user mode PSR with priority 2 and N set
+        vm.memory[0x2FFE] = 0x3010;
+        vm.memory[0x2FFF] = 0x8204;
+        vm.set_psr(0x0002);
+        vm.set_pc(0x0500);
+        vm.registers[6] = 0x2FFE;
+        vm.saved_usp = 0xF000;
+
+        vm.step().unwrap();
+
+        assert_eq!(vm.pc(), 0x3010);
+        assert_eq!(vm.psr(), 0x8204);
+        assert_eq!(vm.privilege(), Privilege::User);
+        assert_eq!(vm.priority(), 2);
+        assert_eq!(vm.cond(), Some(ConditionFlag::Neg));
+        assert_eq!(vm.reg(Register::R6), 0xF000);
+        assert_eq!(vm.saved_ssp(), 0x3000);
+
+        // Returning to supervisor mode keeps the supervisor stack
+        vm.memory[0x0500] = 0b1000_0000_0000_0000;
+        vm.memory[0x2FF0] = 0x0600;
+        vm.memory[0x2FF1] = 0x0401;
+        vm.set_psr(0x0000);
+        vm.set_pc(0x0500);
+        vm.registers[6] = 0x2FF0;
+
+        vm.step().unwrap();
+
+        assert_eq!(vm.pc(), 0x0600);
+        assert_eq!(vm.privilege(), Privilege::Supervisor);
+        assert_eq!(vm.priority(), 4);
+        assert_eq!(vm.reg(Register::R6), 0x2FF2);
+    }
+
     #[test]
     fn test_res() {
         let mut vm = VM::new();
@@ -515,13 +1192,81 @@ mod tests {
         // Create a RES instruction
         // Binary representation: 1110 0000 0000 0000
         let instr: u16 = 0b1101_0000_0000_0000;
+        vm.memory[0x3000] = instr;
 
-        vm.execute(OpCode::Res, instr);
+        let result = vm.step();
 
         println!("Registers after RES: {:?}", vm.registers);
+        assert!(matches!(
+            result,
+            Err(VmError::IllegalOpcode {
+                pc: 0x3000,
+                instruction: 0xD000
+            })
+        ));
         assert!(!vm.running);
     }
 
+    #[test]
+    fn test_trap_through_vector_table() {
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Table);
+        vm.memory[0x3000] = 0b1111_0000_0010_0110; // TRAP x26
+        vm.memory[0x0026] = 0x1000;
+        vm.memory[0x1000] = 0b0001_0000_0010_0001; // ADD R0, R0, #1
+        vm.memory[0x1001] = 0b1100_0001_1100_0000; // RET
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.trap, Some(0x26));
+        assert_eq!(vm.pc(), 0x1000);
+        assert_eq!(vm.reg(Register::R7), 0x3001);
+
+        vm.run_for(2).unwrap();
+        assert_eq!(vm.pc(), 0x3001);
+        assert_eq!(vm.reg(Register::R0), 1);
+    }
+
+    #[test]
+    fn test_unknown_trap() {
+        let mut vm = VM::new();
+        vm.memory[0x3000] = 0xF026; // TRAP x26
+
+        let result = vm.run();
+
+        assert!(matches!(
+            result,
+            Err(VmError::UnknownTrap {
+                pc: 0x3000,
+                vector: 0x26
+            })
+        ));
+        assert!(!vm.running);
+    }
+
+    #[test]
+    fn test_trap_getc_end_of_input() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.memory[0x3000] = 0xF020; // GETC
+
+        match vm.step() {
+            Err(VmError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_trap_puts_wraps_around_memory() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+        vm.registers[0] = 0xFFFF;
+        vm.memory[0xFFFF] = 'A' as u16;
+        vm.memory[0x0000] = 0x0000;
+
+        vm.trap_puts().unwrap();
+
+        assert_eq!(console.output_string(), "A");
+    }
+
     #[test]
     fn test_and_register_mode() {
         let mut vm = VM::new();
@@ -558,6 +1303,23 @@ mod tests {
         assert_eq!(vm.registers[0], 0b1000);
     }
 
+    #[test]
+    fn test_and_sets_condition_codes() {
+        let mut vm = VM::new();
+
+        // AND R0, R1, R2 with a negative result
+        vm.registers[1] = 0x8001;
+        vm.registers[2] = 0xF000;
+        vm.and(0b0101_0000_0100_0010);
+        assert_eq!(vm.registers[0], 0x8000);
+        assert_eq!(vm.cond(), Some(ConditionFlag::Neg));
+
+        // AND R0, R0, #0: the usual way to clear a register before branching on it
+        vm.and(0b0101_0000_0010_0000);
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.cond(), Some(ConditionFlag::Zro));
+    }
+
     #[test]
     fn test_not() {
         let mut vm = VM::new();
@@ -679,7 +1441,7 @@ mod tests {
         // Binary representation: 0010 000 000 000010
         let instr: u16 = 0b0010_0000_0000_0010;
 
-        vm.ld(instr);
+        vm.ld(instr).unwrap();
 
         println!("Registers after LD: {:?}", vm.registers);
         println!("Memory after LD: {:?}", &vm.memory[0x3000..0x3002]);
@@ -698,7 +1460,7 @@ mod tests {
         // Binary representation: 0110 000 001 000010
         let instr: u16 = 0b0110_0000_0100_0010;
 
-        vm.ldr(instr);
+        vm.ldr(instr).unwrap();
 
         println!("Registers after LDR: {:?}", vm.registers);
         println!("Memory after LDR: {:?}", &vm.memory[0x3000..0x3002]);
@@ -734,7 +1496,7 @@ mod tests {
         // Binary representation: 0011 000 000 000010
         let instr: u16 = 0b0011_0000_0000_0010;
 
-        vm.st(instr);
+        vm.st(instr).unwrap();
 
         println!("Registers after ST: {:?}", vm.registers);
         println!("Memory after ST: {:?}", &vm.memory[0x3000..0x3002]);
@@ -754,7 +1516,7 @@ mod tests {
         // Binary representation: 1011 000 000 000010
         let instr: u16 = 0b1011_0000_0000_0010;
 
-        vm.sti(instr);
+        vm.sti(instr).unwrap();
 
         println!("Registers after STI: {:?}", vm.registers);
         println!("Memory after STI: {:?}", &vm.memory[0x3000..0x3060]);
@@ -773,7 +1535,7 @@ mod tests {
         // Binary representation: 0111 000 001 000010
         let instr: u16 = 0b0111_0000_0100_0010;
 
-        vm.str(instr);
+        vm.str(instr).unwrap();
 
         println!("Registers after STR: {:?}", vm.registers);
         println!("Memory after STR: {:?}", &vm.memory[0x3000..0x3002]);
@@ -782,7 +1544,8 @@ mod tests {
 
     #[test]
     fn test_trap_puts() {
-        let mut vm = VM::new();
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
         // Set initial value for the register
         vm.registers[0] = 0x3000; // R0
         vm.memory[0x3000] = 'H' as u16;
@@ -800,7 +1563,7 @@ mod tests {
         vm.memory[0x300C] = 0x0000; // Null-terminated string
         println!("Registers before TRAP: {:?}", vm.registers);
 
-        vm.trap_puts();
+        vm.trap_puts().unwrap();
 
         println!("Registers after TRAP: {:?}", vm.registers);
         println!("Memory after TRAP: {:?}", &vm.memory[0x3000..0x300D]);
@@ -811,72 +1574,95 @@ mod tests {
                 0x0064, 0x0021, 0x0000
             ]
         );
+        assert_eq!(console.output_string(), "Hello World!");
     }
 
-    // #[test]
-    // fn test_trap_getc() {
-    //     let mut vm = VM::new();
-    //     // Set initial value for the register
-    //     vm.registers[0] = 0x0000; // R0
-    //     println!("Registers before TRAP: {:?}", vm.registers);
+    #[test]
+    fn test_trap_getc() {
+        let console = MemoryConsole::with_input(b"a");
+        let mut vm = VM::with_console(console.clone());
+        // Set initial value for the register
+        vm.registers[0] = 0x0000; // R0
+        println!("Registers before TRAP: {:?}", vm.registers);
 
-    //     vm.trap_in();
+        vm.trap_getc().unwrap();
 
-    //     println!("Registers after TRAP: {:?}", vm.registers);
-    //     assert_eq!(vm.registers[0], 'a' as u16);
-    // }
+        println!("Registers after TRAP: {:?}", vm.registers);
+        assert_eq!(vm.registers[0], 'a' as u16);
+        assert!(console.output().is_empty());
+    }
 
     #[test]
     fn test_trap_out() {
-        let mut vm = VM::new();
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
         // Set initial value for the register
         vm.registers[0] = 'a' as u16; // R0
         println!("Registers before TRAP: {:?}", vm.registers);
 
-        vm.trap_out();
+        vm.trap_out().unwrap();
 
         println!("Registers after TRAP: {:?}", vm.registers);
         assert_eq!(vm.registers[0], 'a' as u16);
+        assert_eq!(console.output_string(), "a");
     }
 
-    // #[test]
-    // fn test_trap_in() {
-    //     let mut vm = VM::new();
-    //     // Set initial value for the register
-    //     vm.registers[0] = 0x0000; // R0
-    //     println!("Registers before TRAP: {:?}", vm.registers);
+    #[test]
+    fn test_trap_in() {
+        let console = MemoryConsole::with_input(b"a");
+        let mut vm = VM::with_console(console.clone());
+        // Set initial value for the register
+        vm.registers[0] = 0x0000; // R0
+        println!("Registers before TRAP: {:?}", vm.registers);
 
-    //     vm.trap_in();
+        vm.trap_in().unwrap();
 
-    //     println!("Registers after TRAP: {:?}", vm.registers);
-    //     assert_eq!(vm.registers[0], 'a' as u16);
-    // }
+        println!("Registers after TRAP: {:?}", vm.registers);
+        assert_eq!(vm.registers[0], 'a' as u16);
+        assert_eq!(console.output_string(), "Enter a character: ");
+    }
 
     #[test]
     fn test_trap_puts_p() {
-        let mut vm = VM::new();
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
         // Set initial value for the register
         vm.registers[0] = 0x3000; // R0
         vm.memory[0x3000] = 0x4848; // "HH"
         vm.memory[0x3001] = 0x0000; // Null-terminated string
         println!("Registers before TRAP: {:?}", vm.registers);
 
-        vm.trap_puts_p();
+        vm.trap_puts_p().unwrap();
 
         println!("Registers after TRAP: {:?}", vm.registers);
         println!("Memory after TRAP: {:?}", &vm.memory[0x3000..0x3002]);
         assert_eq!(&vm.memory[0x3000..0x3002], &[0x4848, 0x0000]);
+        assert_eq!(console.output_string(), "HH");
     }
 
     #[test]
     fn test_trap_halt() {
-        let mut vm = VM::new();
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
         println!("Registers before TRAP: {:?}", vm.registers);
 
-        vm.trap_halt();
+        vm.trap_halt().unwrap();
 
         println!("Registers after TRAP: {:?}", vm.registers);
         assert!(!vm.running);
+        assert_eq!(console.output_string(), "Halting the VM...\n");
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let console = MemoryConsole::with_input(b"x");
+        let mut vm = VM::with_console(console.clone());
+        vm.memory[0x3000] = 0xF020; // GETC
+        vm.memory[0x3001] = 0xF021; // OUT
+        vm.memory[0x3002] = 0xF025; // HALT
+
+        assert_eq!(vm.run().unwrap(), StopReason::Halted);
+        assert_eq!(console.output_string(), "xHalting the VM...\n");
     }
 
     #[test]
@@ -900,6 +1686,61 @@ mod tests {
         assert_eq!(vm.memory[0x3001], 0x7856);
     }
 
+    #[test]
+    fn test_load_object_past_the_end_of_memory() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.load_object(&ObjectImage {
+            origin: 0xFFFF,
+            words: vec![1, 2],
+        });
+
+        assert_eq!(vm.memory[0xFFFF], 1);
+        assert_eq!(vm.memory[0x0000], 0);
+    }
+
+    #[test]
+    fn test_read_image_errors() {
+        let mut vm = VM::new();
+
+        let result = vm.load_image("missing.obj");
+        assert!(matches!(result, Err(VmError::ImageLoad { offset: 0, .. })));
+
+        let mut file = File::create("test_truncated.obj").unwrap();
+        file.write_all(&[0x30, 0x00, 0x12, 0x34, 0x56]).unwrap();
+        let result = vm.load_image("test_truncated.obj");
+        std::fs::remove_file("test_truncated.obj").unwrap();
+
+        match result {
+            Err(VmError::ImageLoad { path, offset, .. }) => {
+                assert_eq!(path, "test_truncated.obj");
+                assert_eq!(offset, 4);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_image_with_symbols() {
+        let mut vm = VM::new();
+        File::create("test_symbols.obj")
+            .unwrap()
+            .write_all(&[0x30, 0x00, 0xF0, 0x25])
+            .unwrap();
+        File::create("test_symbols.sym")
+            .unwrap()
+            .write_all(b"// Symbol table\n//\tMAIN              3000\n")
+            .unwrap();
+
+        let result = vm.load_image("test_symbols.obj");
+        std::fs::remove_file("test_symbols.obj").unwrap();
+        std::fs::remove_file("test_symbols.sym").unwrap();
+
+        assert_eq!(result.unwrap(), 0x3000);
+        assert_eq!(vm.memory[0x3000], 0xF025);
+        assert_eq!(vm.symbols().address_of("MAIN"), Some(0x3000));
+        assert_eq!(vm.symbols().format_address(0x3001), "MAIN+1");
+    }
+
     #[test]
     fn test_mem_write() {
         let mut vm = VM::new();
@@ -907,43 +1748,606 @@ mod tests {
         vm.memory[0x3000] = 0x1234;
         println!("Memory before write: {:?}", &vm.memory[0x3000..0x3001]);
 
-        vm.mem_write(0x3000, 0x5678);
+        vm.mem_write(0x3000, 0x5678).unwrap();
 
         println!("Memory after write: {:?}", &vm.memory[0x3000..0x3001]);
         assert_eq!(vm.memory[0x3000], 0x5678);
     }
 
-    // #[test]
-    // fn test_mem_read_kbsr() {
-    //     let mut vm = VM::new();
-    //     // Set initial value for the memory
-    //     vm.memory[usize::from(MemoryMappedRegister::Kbsr)] = 0x8000;
-    //     println!(
-    //         "Memory before read: {:?}",
-    //         &vm.memory[MemoryMappedRegister::Kbsr.into()..]
-    //     );
-
-    //     let value = vm.mem_read(MemoryMappedRegister::Kbsr.into());
-
-    //     println!("Value after read: {:?}", value);
-    //     assert_eq!(value, 0x8000);
-    // }
+    #[test]
+    fn test_mem_read_kbsr() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
 
-    // #[test]
-    // fn test_mem_read_kbddr() {
-    //     let mut vm = VM::new();
-    //     // Set initial value for the memory
-    //     vm.memory[usize::from(MemoryMappedRegister::Kbddr)] = 'a' as u16;
-    //     println!(
-    //         "Memory before read: {:?}",
-    //         &vm.memory[MemoryMappedRegister::Kbddr.into()..]
-    //     );
-
-    //     let value = vm.mem_read(MemoryMappedRegister::Kbddr.into());
-
-    //     println!("Value after read: {:?}", value);
-    //     assert_eq!(value, 'a' as u16);
-    // }
+        // No key pressed: the status poll must not block
+        let value = vm.mem_read(MemoryMappedRegister::Kbsr.into()).unwrap();
+        assert_eq!(value, 0);
+
+        console.push_input(b"a");
+        let value = vm.mem_read(MemoryMappedRegister::Kbsr.into()).unwrap();
+        println!("Value after read: {:?}", value);
+        assert_eq!(value, 0x8000);
+
+        // Polling again does not consume the key
+        let value = vm.mem_read(MemoryMappedRegister::Kbsr.into()).unwrap();
+        assert_eq!(value, 0x8000);
+    }
+
+    #[test]
+    fn test_exceptions_vector_to_handlers() {
+        let mut vm = VM::new();
+        vm.set_exception_mode(ExceptionMode::Vector);
+        vm.memory[0x0100] = 0x1000;
+        vm.memory[0x0101] = 0x1100;
+        vm.memory[0x3000] = 0b1101_0000_0000_0000; // RES
+        vm.memory[0x1100] = 0b1000_0000_0000_0000; // RTI
+        vm.memory[0x3001] = 0b1000_0000_0000_0000; // RTI in user mode
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.exception, Some(ILLEGAL_OPCODE_VECTOR));
+        assert_eq!(result.pc_after, 0x1100);
+        assert_eq!(vm.privilege(), Privilege::Supervisor);
+        assert_eq!(vm.memory[0x2FFE], 0x3001);
+
+        // The handler returns to the instruction after the faulting one
+        vm.step().unwrap();
+        assert_eq!(vm.pc(), 0x3001);
+        assert_eq!(vm.privilege(), Privilege::User);
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.exception, Some(PRIVILEGE_VIOLATION_VECTOR));
+        assert_eq!(vm.pc(), 0x1000);
+        assert!(vm.running);
+    }
+
+    #[test]
+    fn test_access_control() {
+        let mut vm = VM::new();
+        vm.set_access_control(true);
+        vm.memory[0x3000] = 0b0110_0000_0100_0000; // LDR R0, R1, #0
+        vm.registers[1] = 0xFE00;
+
+        let result = vm.step();
+        assert!(matches!(
+            result,
+            Err(VmError::AccessViolation {
+                pc: 0x3000,
+                address: 0xFE00
+            })
+        ));
+
+        // With an OS, the fetch of a user instruction in system space vectors to x02
+        let mut vm = VM::new();
+        vm.set_access_control(true);
+        vm.set_exception_mode(ExceptionMode::Vector);
+        vm.memory[0x0102] = 0x1000;
+        vm.memory[0x1000] = 0b1111_0000_0010_0101; // HALT
+        vm.set_pc(0x0200);
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.interrupt, Some(ACCESS_VIOLATION_VECTOR));
+        assert_eq!(result.pc_before, 0x1000);
+        assert_eq!(vm.memory[0x2FFE], 0x0200);
+
+        // Supervisor mode may access everything
+        let mut vm = VM::new();
+        vm.set_access_control(true);
+        vm.set_psr(0x0002);
+        vm.memory[0x3000] = 0b0110_0000_0100_0000; // LDR R0, R1, #0
+        vm.registers[1] = 0x0000;
+        vm.step().unwrap();
+    }
+
+    #[test]
+    fn test_trap_through_vector_table_with_access_control() {
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Table);
+        vm.set_access_control(true);
+        vm.memory[0x3000] = 0b1111_0000_0010_0110; // TRAP x26
+        vm.memory[0x0026] = 0x1000;
+
+        // TRAP does not leave user mode, so reading the table is a violation
+        let result = vm.step();
+        assert!(matches!(
+            result,
+            Err(VmError::AccessViolation {
+                pc: 0x3000,
+                address: 0x0026
+            })
+        ));
+        assert_eq!(vm.reg(Register::R7), 0x0000);
+
+        // Supervisor mode goes through the table as usual
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Table);
+        vm.set_access_control(true);
+        vm.set_psr(0x0002);
+        vm.memory[0x3000] = 0b1111_0000_0010_0110; // TRAP x26
+        vm.memory[0x0026] = 0x1000;
+        vm.step().unwrap();
+        assert_eq!(vm.pc(), 0x1000);
+    }
+
+    #[test]
+    fn test_keyboard_at_end_of_input() {
+        let mut vm = VM::with_console(StreamConsole::new(b"a", Vec::new()));
+        let kbsr = MemoryMappedRegister::Kbsr.into();
+        let kbdr = MemoryMappedRegister::Kbddr.into();
+
+        assert_eq!(vm.mem_read(kbdr).unwrap(), 'a' as u16);
+        // No more input will come: a key shows as ready, and reading it fails
+        assert_eq!(vm.mem_read(kbsr).unwrap(), 0x8000);
+        match vm.mem_read(kbdr) {
+            Err(VmError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("expected the end of the input, got {:?}", other),
+        }
+
+        // More input may still come to a memory console
+        let mut vm = VM::with_console(MemoryConsole::new());
+        assert_eq!(vm.mem_read(kbsr).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_keyboard_interrupt() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+        vm.memory[0x3000] = 0b0000_1111_1111_1111; // BRnzp #-1
+        vm.memory[0x0180] = 0x1000;
+        vm.memory[0x1000] = 0b1010_0000_0000_0001; // LDI R0, #1
+        vm.memory[0x1001] = 0b1000_0000_0000_0000; // RTI
+        vm.memory[0x1002] = MemoryMappedRegister::Kbddr.into();
+        vm.registers[6] = 0xF000;
+        vm.mem_write(MemoryMappedRegister::Kbsr.into(), 0xFFFF)
+            .unwrap();
+        assert_eq!(vm.read_memory(MemoryMappedRegister::Kbsr.into()), 0x4000);
+
+        // No key: the program keeps running
+        assert_eq!(vm.step().unwrap().interrupt, None);
+
+        console.push_input(b"a");
+        let result = vm.step().unwrap();
+        assert_eq!(result.interrupt, Some(KEYBOARD_VECTOR));
+        assert_eq!(result.pc_before, 0x1000);
+        assert_eq!(vm.reg(Register::R0), 'a' as u16);
+        assert_eq!(vm.privilege(), Privilege::Supervisor);
+        assert_eq!(vm.priority(), KEYBOARD_PRIORITY);
+        assert_eq!(vm.reg(Register::R6), 0x2FFE);
+        assert_eq!(vm.saved_usp(), 0xF000);
+        assert_eq!(vm.memory[0x2FFF], 0x8002);
+        assert_eq!(vm.memory[0x2FFE], 0x3000);
+
+        vm.step().unwrap();
+        assert_eq!(vm.pc(), 0x3000);
+        assert_eq!(vm.psr(), 0x8002);
+        assert_eq!(vm.reg(Register::R6), 0xF000);
+        assert_eq!(vm.step().unwrap().interrupt, None);
+
+        // Not taken while running at a higher priority
+        vm.set_psr(0x8502);
+        console.push_input(b"b");
+        assert_eq!(vm.step().unwrap().interrupt, None);
+        vm.set_psr(0x8302);
+        assert_eq!(vm.step().unwrap().interrupt, Some(KEYBOARD_VECTOR));
+    }
+
+    #[test]
+    fn test_display_and_machine_control() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+
+        assert_eq!(
+            vm.mem_read(MemoryMappedRegister::Dsr.into()).unwrap(),
+            0x8000
+        );
+        vm.mem_write(MemoryMappedRegister::Ddr.into(), 'h' as u16)
+            .unwrap();
+        vm.mem_write(MemoryMappedRegister::Ddr.into(), 'i' as u16)
+            .unwrap();
+        assert_eq!(console.output_string(), "hi");
+
+        assert_eq!(
+            vm.mem_read(MemoryMappedRegister::Mcr.into()).unwrap(),
+            0x8000
+        );
+        vm.mem_write(MemoryMappedRegister::Mcr.into(), 0x8001)
+            .unwrap();
+        assert!(vm.running);
+        vm.mem_write(MemoryMappedRegister::Mcr.into(), 0x0001)
+            .unwrap();
+        assert!(!vm.running);
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut vm = VM::new();
+        vm.memory[0x3000] = 0b0000_1111_1111_1111; // BRnzp #-1
+        vm.memory[0x0181] = 0x1000;
+        vm.memory[0x1000] = 0b1010_0000_0000_0010; // LDI R0, #2 (acknowledge)
+        vm.memory[0x1001] = 0b1000_0000_0000_0000; // RTI
+        vm.memory[0x1003] = MemoryMappedRegister::Tsr.into();
+        vm.mem_write(MemoryMappedRegister::Tir.into(), 3).unwrap();
+        vm.mem_write(MemoryMappedRegister::Tsr.into(), 0x4001)
+            .unwrap();
+
+        let results: Vec<StepResult> = (0..4).map(|_| vm.step().unwrap()).collect();
+        assert_eq!(vm.mem_read(MemoryMappedRegister::Tcr.into()).unwrap(), 2);
+        assert_eq!(results[3].interrupt, Some(TIMER_VECTOR));
+        assert_eq!(vm.priority(), TIMER_PRIORITY);
+        assert_eq!(vm.reg(Register::R0), 0xC001);
+
+        vm.step().unwrap();
+        assert_eq!(vm.pc(), 0x3000);
+        assert_eq!(vm.priority(), 0);
+
+        // The vector and priority are configurable
+        vm.mem_write(MemoryMappedRegister::Tvr.into(), 0x0790)
+            .unwrap();
+        vm.memory[0x0190] = 0x1000;
+        let result = vm.run_until(|_, result| result.interrupt.is_some());
+        assert!(result.is_ok());
+        assert_eq!(vm.priority(), 7);
+        assert_eq!(vm.memory[0x2FFE], 0x3000);
+    }
+
+    #[test]
+    fn test_custom_device() {
+        /* a register at xFE10 counting instructions; writing it resets the count */
+        struct Counter(Rc<Cell<u16>>);
+
+        impl Device for Counter {
+            fn addresses(&self) -> Vec<u16> {
+                vec![0xFE10]
+            }
+
+            fn read(
+                &mut self,
+                _address: u16,
+                _context: &mut DeviceContext,
+            ) -> Result<u16, VmError> {
+                Ok(self.0.get())
+            }
+
+            fn write(
+                &mut self,
+                _address: u16,
+                value: u16,
+                _context: &mut DeviceContext,
+            ) -> Result<(), VmError> {
+                self.0.set(value);
+                Ok(())
+            }
+
+            fn peek(&self, _address: u16) -> u16 {
+                self.0.get()
+            }
+
+            fn tick(&mut self, _context: &mut DeviceContext) -> Result<(), VmError> {
+                self.0.set(self.0.get() + 1);
+                Ok(())
+            }
+        }
+
+        let count = Rc::new(Cell::new(0));
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.add_device(Counter(count.clone()));
+        vm.memory[0x3000] = 0b0001_0000_0010_0000; // ADD R0, R0, #0
+        vm.memory[0x3001] = 0b1010_0010_0000_0010; // LDI R1, #2
+        vm.memory[0x3002] = 0b1011_0100_0000_0001; // STI R2, #1
+        vm.memory[0x3004] = 0xFE10;
+
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.reg(Register::R1), 1);
+        assert_eq!(count.get(), 2);
+
+        vm.set_reg(Register::R2, 0x0100);
+        let result = vm.step().unwrap();
+        assert_eq!(
+            result.memory_writes,
+            vec![MemoryWrite {
+                address: 0xFE10,
+                old: 2,
+                new: 0x0100
+            }]
+        );
+        assert_eq!(count.get(), 0x0101);
+        assert_eq!(vm.read_memory(0xFE10), 0x0101);
+        assert_eq!(vm.memory[0xFE10], 0);
+    }
+
+    #[test]
+    fn test_replaced_device_is_unplugged() {
+        /* counts its ticks, and shows the count in all of its registers */
+        struct Ticks(Vec<u16>, Rc<Cell<u16>>);
+
+        impl Device for Ticks {
+            fn addresses(&self) -> Vec<u16> {
+                self.0.clone()
+            }
+
+            fn read(
+                &mut self,
+                _address: u16,
+                _context: &mut DeviceContext,
+            ) -> Result<u16, VmError> {
+                Ok(self.1.get())
+            }
+
+            fn write(
+                &mut self,
+                _address: u16,
+                _value: u16,
+                _context: &mut DeviceContext,
+            ) -> Result<(), VmError> {
+                Ok(())
+            }
+
+            fn peek(&self, _address: u16) -> u16 {
+                self.1.get()
+            }
+
+            fn tick(&mut self, _context: &mut DeviceContext) -> Result<(), VmError> {
+                self.1.set(self.1.get() + 1);
+                Ok(())
+            }
+        }
+
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+        vm.memory[0x3000] = 0b0000_1111_1111_1111; // BRnzp #-1
+        vm.memory[0x0180] = 0x1000;
+        vm.mem_write(MemoryMappedRegister::Kbsr.into(), 0x4000)
+            .unwrap();
+        let keyboard = vec![
+            MemoryMappedRegister::Kbsr.into(),
+            MemoryMappedRegister::Kbddr.into(),
+        ];
+
+        // The old keyboard would raise x80 for this key
+        let first = Rc::new(Cell::new(0));
+        vm.add_device(Ticks(keyboard.clone(), first.clone()));
+        console.push_input(b"a");
+        assert_eq!(vm.step().unwrap().interrupt, None);
+        assert_eq!(first.get(), 1);
+
+        let second = Rc::new(Cell::new(0));
+        vm.add_device(Ticks(keyboard, second.clone()));
+        vm.run_for(3).unwrap();
+        assert_eq!(first.get(), 1);
+        assert_eq!(second.get(), 3);
+        assert_eq!(vm.read_memory(MemoryMappedRegister::Kbsr.into()), 3);
+
+        // A device keeping some of its registers stays plugged in
+        let third = Rc::new(Cell::new(0));
+        vm.add_device(Ticks(vec![MemoryMappedRegister::Kbsr.into()], third));
+        vm.step().unwrap();
+        assert_eq!(second.get(), 4);
+        assert_eq!(vm.read_memory(MemoryMappedRegister::Kbddr.into()), 4);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.memory[0x3000] = 0b0000_1111_1111_1111; // BRnzp #-1
+        vm.set_limits(Limits {
+            max_instructions: Some(10),
+            ..Limits::default()
+        });
+        match vm.run() {
+            Err(VmError::LimitExceeded { limit, pc }) => {
+                assert_eq!(limit, Limit::Instructions(10));
+                assert_eq!(pc, 0x3000);
+            }
+            other => panic!("expected the instruction limit, got {:?}", other),
+        }
+        assert_eq!(vm.instruction_count(), 10);
+        assert!(!vm.is_running());
+
+        vm.set_running(true);
+        vm.set_limits(Limits {
+            max_time: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            vm.run(),
+            Err(VmError::LimitExceeded {
+                limit: Limit::Time(_),
+                pc: 0x3000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let console = MemoryConsole::new();
+        let mut vm = VM::with_console(console.clone());
+        vm.memory[0x3000] = 0xF021; // OUT
+        vm.memory[0x3001] = 0b0000_1111_1111_1110; // BRnzp #-2
+        vm.set_reg(Register::R0, 'x' as u16);
+        vm.set_limits(Limits {
+            max_output: Some(3),
+            ..Limits::default()
+        });
+
+        match vm.run() {
+            Err(e @ VmError::LimitExceeded { .. }) => {
+                assert_eq!(e.to_string(), "output limit of 3 bytes exceeded at x3001");
+            }
+            other => panic!("expected the output limit, got {:?}", other),
+        }
+        assert_eq!(console.output_string(), "xxx");
+        assert_eq!(vm.instruction_count(), 7);
+    }
+
+    #[test]
+    fn test_mem_read_kbddr() {
+        let console = MemoryConsole::with_input(b"ab");
+        let mut vm = VM::with_console(console.clone());
+
+        assert_eq!(
+            vm.mem_read(MemoryMappedRegister::Kbsr.into()).unwrap(),
+            0x8000
+        );
+        let value = vm.mem_read(MemoryMappedRegister::Kbddr.into()).unwrap();
+        println!("Value after read: {:?}", value);
+        assert_eq!(value, 'a' as u16);
+        assert_eq!(vm.read_memory(MemoryMappedRegister::Kbsr.into()), 0);
+
+        // One key press is one byte
+        assert_eq!(
+            vm.mem_read(MemoryMappedRegister::Kbsr.into()).unwrap(),
+            0x8000
+        );
+        assert_eq!(
+            vm.mem_read(MemoryMappedRegister::Kbddr.into()).unwrap(),
+            'b' as u16
+        );
+        assert_eq!(vm.mem_read(MemoryMappedRegister::Kbsr.into()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut vm = VM::new();
+        vm.memory[0x3000] = 0b0001_0000_0010_0011; // ADD R0, R0, #3
+        vm.memory[0x3001] = 0b0011_0000_0000_0001; // ST R0, #1
+        vm.memory[0x3002] = 0b0110_0010_0000_0001; // LDR R1, R0, #1
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.pc_before, 0x3000);
+        assert_eq!(result.pc_after, 0x3001);
+        assert_eq!(result.opcode, OpCode::Add);
+        assert_eq!(
+            result.register_writes,
+            vec![
+                RegisterWrite {
+                    register: Register::R0,
+                    old: 0,
+                    new: 3
+                },
+                RegisterWrite {
+                    register: Register::Cond,
+                    old: ConditionFlag::Zro.into(),
+                    new: ConditionFlag::Pos.into()
+                },
+            ]
+        );
+        assert!(result.memory_reads.is_empty());
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.opcode, OpCode::St);
+        assert_eq!(
+            result.memory_writes,
+            vec![MemoryWrite {
+                address: 0x3003,
+                old: 0,
+                new: 3
+            }]
+        );
+
+        let result = vm.step().unwrap();
+        assert_eq!(result.opcode, OpCode::Ldr);
+        assert_eq!(
+            result.memory_reads,
+            vec![MemoryRead {
+                address: 0x0004,
+                value: 0
+            }]
+        );
+        assert_eq!(result.trap, None);
+        assert!(!result.halted);
+    }
+
+    #[test]
+    fn test_run_for_and_run_until() {
+        let mut vm = VM::new();
+        // BR to itself: an infinite loop
+        vm.memory[0x3000] = 0b0000_1111_1111_1111;
+
+        assert_eq!(vm.run_for(10).unwrap(), StopReason::StepLimit);
+        assert_eq!(vm.pc(), 0x3000);
+
+        let mut count = 0;
+        let reason = vm
+            .run_until(|_, _| {
+                count += 1;
+                count == 5
+            })
+            .unwrap();
+        assert_eq!(reason, StopReason::Predicate);
+        assert_eq!(count, 5);
+
+        vm.running = false;
+        assert_eq!(vm.run_for(10).unwrap(), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut vm = VM::new();
+        vm.memory[0x3000] = 0b0010_0010_0000_0011; // LD R1, #3 -> reads x3004
+        vm.memory[0x3001] = 0b0001_0010_0110_0001; // ADD R1, R1, #1
+        vm.memory[0x3002] = 0b0011_0010_0000_0010; // ST R1, #2 -> writes x3005
+        vm.memory[0x3003] = 0b0000_1111_1111_1100; // BRnzp #-4
+        vm.memory[0x3004] = 7;
+        vm.add_watchpoint(Watchpoint::new(0x3004, 0x3005, WatchKind::Write));
+
+        assert_eq!(vm.run().unwrap(), StopReason::Watchpoint);
+        assert_eq!(vm.pc(), 0x3003);
+        assert_eq!(vm.memory[0x3005], 8);
+
+        let result = vm.step().unwrap();
+        assert!(result.watch_hits.is_empty());
+
+        vm.add_watchpoint(Watchpoint::at(0x3004, WatchKind::Read));
+        let result = vm.step().unwrap();
+        assert_eq!(
+            result.watch_hits,
+            vec![WatchHit {
+                address: 0x3004,
+                kind: WatchKind::Read,
+                old: 7,
+                new: 7
+            }]
+        );
+
+        vm.step().unwrap();
+        let result = vm.step().unwrap();
+        assert_eq!(
+            result.watch_hits,
+            vec![WatchHit {
+                address: 0x3005,
+                kind: WatchKind::Write,
+                old: 8,
+                new: 8
+            }]
+        );
+
+        assert_eq!(vm.remove_watchpoints_at(0x3004), 2);
+        assert_eq!(vm.run_for(20).unwrap(), StopReason::StepLimit);
+    }
+
+    #[test]
+    fn test_register_accessors() {
+        let mut vm = VM::new();
+        assert_eq!(vm.pc(), PC_START);
+        assert_eq!(vm.cond(), Some(ConditionFlag::Zro));
+
+        vm.set_reg(Register::R3, 0x1234);
+        vm.set_pc(0x4000);
+
+        assert_eq!(vm.reg(Register::R3), 0x1234);
+        assert_eq!(vm.registers[3], 0x1234);
+        assert_eq!(vm.reg(Register::PC), 0x4000);
+    }
+
+    #[test]
+    fn test_memory_accessors() {
+        let mut vm = VM::new();
+        vm.write_memory(0x3000, 0xBEEF);
+
+        assert_eq!(vm.read_memory(0x3000), 0xBEEF);
+        assert_eq!(vm.memory()[0x3000], 0xBEEF);
+        assert_eq!(vm.memory().len(), MEMORY_SIZE);
+    }
 
     #[test]
     fn test_mem_read() {
@@ -952,7 +2356,7 @@ mod tests {
         vm.memory[0x3000] = 0x1234;
         println!("Memory before read: {:?}", &vm.memory[0x3000..0x3001]);
 
-        let value = vm.mem_read(0x3000);
+        let value = vm.mem_read(0x3000).unwrap();
 
         println!("Value after read: {:?}", value);
         assert_eq!(value, 0x1234);
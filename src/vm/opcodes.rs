@@ -1,7 +1,9 @@
 // Module for the opcodes of the LC3
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum OpCode {
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
     Br = 0, /* branch */
     Add,    /* add  */
     Ld,     /* load */
@@ -51,3 +53,39 @@ impl TryFrom<u16> for OpCode {
         })
     }
 }
+
+impl OpCode {
+    /// The assembly name of the opcode, e.g. `"ADD"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Br => "BR",
+            Self::Add => "ADD",
+            Self::Ld => "LD",
+            Self::St => "ST",
+            Self::Jsr => "JSR",
+            Self::And => "AND",
+            Self::Ldr => "LDR",
+            Self::Str => "STR",
+            Self::Rti => "RTI",
+            Self::Not => "NOT",
+            Self::Ldi => "LDI",
+            Self::Sti => "STI",
+            Self::Jmp => "JMP",
+            Self::Res => "RES",
+            Self::Lea => "LEA",
+            Self::Trap => "TRAP",
+        }
+    }
+}
+
+impl FromStr for OpCode {
+    type Err = &'static str;
+
+    /// Parses an opcode name, in any case.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        (0..16)
+            .filter_map(|value| OpCode::try_from(value).ok())
+            .find(|op| op.name().eq_ignore_ascii_case(name))
+            .ok_or("invalid opcode name")
+    }
+}
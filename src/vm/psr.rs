@@ -0,0 +1,30 @@
+// Module for the Processor Status Register
+
+/*
+    15 | 14 13 12 11 | 10 9 8   | 7 6 5 4 3 | 2 1 0
+    Pr | 0  0  0  0  | Priority | 0 0 0 0 0 | N Z P
+*/
+pub(crate) const PRIVILEGE_BIT: u16 = 1 << 15;
+pub(crate) const PRIORITY_SHIFT: u16 = 8;
+pub(crate) const PRIORITY_MASK: u16 = 0x7;
+pub(crate) const CONDITION_MASK: u16 = 0x7;
+
+/* the supervisor stack grows down from the start of user space */
+pub const INITIAL_SSP: u16 = 0x3000;
+
+/// The privilege mode the processor runs in, bit 15 of the PSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    Supervisor = 0,
+    User = 1,
+}
+
+impl Privilege {
+    pub(crate) fn from_psr(psr: u16) -> Self {
+        if psr & PRIVILEGE_BIT != 0 {
+            Privilege::User
+        } else {
+            Privilege::Supervisor
+        }
+    }
+}
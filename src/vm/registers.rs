@@ -1,6 +1,6 @@
 // Module for the registers of the LC3
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
     R0 = 0, /* general purpose registers */
     R1,
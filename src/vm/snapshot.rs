@@ -0,0 +1,306 @@
+// Module for VM snapshots: the whole machine state, saved to and restored from a file
+//
+// The format is versioned and big-endian, like object images:
+//
+//   "LC3S", version (u16)
+//   R0-R7, PC, COND (10 x u16)
+//   running, privilege, priority, saved USP, saved SSP (5 x u16)
+//   exception mode, access control, trap mode (3 x u16)
+//   instructions executed (u64)
+//   memory (65536 x u16)
+//   device count (u16), then for each device: state length (u16) and state words
+//   symbol table length (u32), then the symbol table in the .sym format
+
+use super::{ExceptionMode, Privilege, SymbolTable, TrapMode, VmError, MEMORY_SIZE, VM};
+use std::fs;
+use std::io;
+
+const MAGIC: &[u8; 4] = b"LC3S";
+const SNAPSHOT_VERSION: u16 = 1;
+
+/* the saved state, parsed in full before any of it is applied */
+struct Snapshot {
+    registers: [u16; 10],
+    running: bool,
+    privilege: Privilege,
+    priority: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+    exception_mode: ExceptionMode,
+    access_control: bool,
+    trap_mode: TrapMode,
+    instructions: u64,
+    memory: Vec<u16>,
+    devices: Vec<Vec<u16>>,
+    symbols: SymbolTable,
+}
+
+/* reads big-endian values, failing on truncated input */
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("truncated snapshot"));
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn word(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn words(&mut self, len: usize) -> io::Result<Vec<u16>> {
+        (0..len).map(|_| self.word()).collect()
+    }
+
+    fn flag(&mut self) -> io::Result<bool> {
+        match self.word()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(invalid(&format!("invalid flag {}", other))),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Snapshot {
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.bytes(4)? != MAGIC {
+            return Err(invalid("not a snapshot file"));
+        }
+        let version = reader.word()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid(&format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+
+        let mut registers = [0; 10];
+        registers.copy_from_slice(&reader.words(10)?);
+        let running = reader.flag()?;
+        let privilege = match reader.flag()? {
+            false => Privilege::Supervisor,
+            true => Privilege::User,
+        };
+        let priority = reader.word()?;
+        let saved_usp = reader.word()?;
+        let saved_ssp = reader.word()?;
+        let exception_mode = match reader.flag()? {
+            false => ExceptionMode::Abort,
+            true => ExceptionMode::Vector,
+        };
+        let access_control = reader.flag()?;
+        let trap_mode = match reader.flag()? {
+            false => TrapMode::Native,
+            true => TrapMode::Table,
+        };
+        let instructions = reader
+            .words(4)?
+            .into_iter()
+            .fold(0, |count, word| count << 16 | u64::from(word));
+        let memory = reader.words(MEMORY_SIZE)?;
+
+        let device_count = reader.word()?;
+        let mut devices = Vec::new();
+        for _ in 0..device_count {
+            let len = reader.word()?;
+            devices.push(reader.words(len as usize)?);
+        }
+
+        let symbols_len = u32::from_be_bytes(reader.bytes(4)?.try_into().unwrap());
+        let symbols = std::str::from_utf8(reader.bytes(symbols_len as usize)?)
+            .map_err(|_| invalid("symbol table is not UTF-8"))?;
+        let symbols = SymbolTable::parse(symbols).map_err(|e| invalid(&e))?;
+        if !reader.bytes.is_empty() {
+            return Err(invalid("trailing data after the snapshot"));
+        }
+
+        Ok(Snapshot {
+            registers,
+            running,
+            privilege,
+            priority,
+            saved_usp,
+            saved_ssp,
+            exception_mode,
+            access_control,
+            trap_mode,
+            instructions,
+            memory,
+            devices,
+            symbols,
+        })
+    }
+}
+
+impl VM {
+    /// The complete state of the machine: registers, PSR, memory, devices, symbols and
+    /// the instruction count. The console, limits and watchpoints are not included.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut words: Vec<u16> = self.registers.to_vec();
+        words.extend([
+            self.running as u16,
+            self.privilege as u16,
+            self.priority,
+            self.saved_usp,
+            self.saved_ssp,
+            (self.exception_mode == ExceptionMode::Vector) as u16,
+            self.access_control as u16,
+            (self.trap_mode == TrapMode::Table) as u16,
+        ]);
+        words.extend((0..4).rev().map(|i| (self.instructions >> (16 * i)) as u16));
+        words.extend_from_slice(&self.memory);
+        let devices = self.bus.save_states();
+        words.push(devices.len() as u16);
+        for state in devices {
+            words.push(state.len() as u16);
+            words.extend(state);
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        for word in words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        let symbols = self.symbols.to_sym_string();
+        bytes.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(symbols.as_bytes());
+        bytes
+    }
+
+    /// Restores a state returned by `snapshot`. Nothing changes if it is invalid or was
+    /// taken with different devices attached.
+    pub fn restore_snapshot(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let snapshot = Snapshot::parse(bytes)?;
+        self.bus
+            .restore_states(&snapshot.devices)
+            .map_err(|e| invalid(&e))?;
+        self.registers = snapshot.registers;
+        self.running = snapshot.running;
+        self.privilege = snapshot.privilege;
+        self.priority = snapshot.priority;
+        self.saved_usp = snapshot.saved_usp;
+        self.saved_ssp = snapshot.saved_ssp;
+        self.exception_mode = snapshot.exception_mode;
+        self.access_control = snapshot.access_control;
+        self.trap_mode = snapshot.trap_mode;
+        self.instructions = snapshot.instructions;
+        self.memory.copy_from_slice(&snapshot.memory);
+        self.symbols = snapshot.symbols;
+        self.deadline = None;
+        self.history.clear();
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.snapshot())
+    }
+
+    pub fn load_snapshot(&mut self, path: &str) -> Result<(), VmError> {
+        let snapshot_error = |source| VmError::SnapshotLoad {
+            path: path.to_string(),
+            source,
+        };
+        let bytes = fs::read(path).map_err(snapshot_error)?;
+        self.restore_snapshot(&bytes).map_err(snapshot_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{MemoryConsole, MemoryMappedRegister, Register};
+
+    #[test]
+    fn test_round_trip() {
+        let mut vm = VM::with_console(MemoryConsole::new());
+        vm.memory[0x3000] = 0b0001_0000_0010_0001; // ADD R0, R0, #1
+        vm.memory[0x3001] = 0b0000_1111_1111_1110; // BRnzp #-2
+        vm.symbols_mut().insert("LOOP", 0x3000);
+        vm.set_trap_mode(TrapMode::Table);
+        vm.set_saved_ssp(0x2F00);
+        vm.mem_write(MemoryMappedRegister::Tir.into(), 50).unwrap();
+        vm.mem_write(MemoryMappedRegister::Tsr.into(), 0x0001)
+            .unwrap();
+        vm.run_for(7).unwrap();
+        let bytes = vm.snapshot();
+
+        let mut copy = VM::with_console(MemoryConsole::new());
+        copy.restore_snapshot(&bytes).unwrap();
+        assert_eq!(copy.snapshot(), bytes);
+        assert_eq!(copy.registers, vm.registers);
+        assert_eq!(copy.instruction_count(), 7);
+        assert_eq!(copy.trap_mode(), TrapMode::Table);
+        assert_eq!(copy.saved_ssp(), 0x2F00);
+        assert_eq!(copy.symbols().address_of("LOOP"), Some(0x3000));
+        assert_eq!(copy.read_memory(MemoryMappedRegister::Tcr.into()), 43);
+
+        /* both machines carry on identically */
+        vm.run_for(10).unwrap();
+        copy.run_for(10).unwrap();
+        assert_eq!(copy.reg(Register::R0), vm.reg(Register::R0));
+        assert_eq!(copy.snapshot(), vm.snapshot());
+    }
+
+    #[test]
+    fn test_invalid_snapshots_change_nothing() {
+        let vm = VM::with_console(MemoryConsole::new());
+        let bytes = vm.snapshot();
+        let mut target = VM::with_console(MemoryConsole::new());
+        target.set_pc(0x4000);
+
+        let error =
+            |bytes: &[u8], target: &mut VM| target.restore_snapshot(bytes).unwrap_err().to_string();
+        assert_eq!(error(b"LC3X", &mut target), "not a snapshot file");
+        assert_eq!(
+            error(b"LC3S\x00\x02", &mut target),
+            "unsupported snapshot version 2"
+        );
+        assert_eq!(
+            error(&bytes[..bytes.len() - 1], &mut target),
+            "truncated snapshot"
+        );
+
+        struct Extra;
+        impl crate::vm::Device for Extra {
+            fn addresses(&self) -> Vec<u16> {
+                vec![0xFE20]
+            }
+            fn read(
+                &mut self,
+                _address: u16,
+                _context: &mut crate::vm::DeviceContext,
+            ) -> Result<u16, VmError> {
+                Ok(0)
+            }
+            fn write(
+                &mut self,
+                _address: u16,
+                _value: u16,
+                _context: &mut crate::vm::DeviceContext,
+            ) -> Result<(), VmError> {
+                Ok(())
+            }
+            fn peek(&self, _address: u16) -> u16 {
+                0
+            }
+        }
+        target.add_device(Extra);
+        assert_eq!(
+            error(&bytes, &mut target),
+            "4 devices saved, but 5 attached"
+        );
+        assert_eq!(target.pc(), 0x4000);
+    }
+}
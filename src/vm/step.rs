@@ -0,0 +1,87 @@
+// Module describing the effects of executing a single instruction
+
+use super::{OpCode, Register, WatchHit};
+
+/// A register written by an instruction, with its value before and after the write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub register: Register,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A memory location read by an instruction, with the value it read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRead {
+    pub address: u16,
+    pub value: u16,
+}
+
+/// A memory location written by an instruction, with its value before and after the write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// Everything that happened while executing one instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepResult {
+    /// Address the instruction was fetched from.
+    pub pc_before: u16,
+    /// Address of the next instruction to execute.
+    pub pc_after: u16,
+    /// The raw instruction word.
+    pub instruction: u16,
+    pub opcode: OpCode,
+    /// General purpose and COND register writes, in execution order. PC is not included.
+    pub register_writes: Vec<RegisterWrite>,
+    /// Memory read by the instruction, not counting the instruction fetch.
+    pub memory_reads: Vec<MemoryRead>,
+    pub memory_writes: Vec<MemoryWrite>,
+    /// Accesses that triggered a watchpoint, in execution order.
+    pub watch_hits: Vec<WatchHit>,
+    /// The trap vector, if the instruction was a TRAP.
+    pub trap: Option<u16>,
+    /// The vector of the interrupt (or of the exception raised by fetching the instruction)
+    /// taken just before the instruction, if any. The instruction is then the first one of
+    /// the service routine.
+    pub interrupt: Option<u8>,
+    /// The vector of the exception raised by the instruction, if any, when exceptions
+    /// are handled by the OS. `pc_after` is then the start of the handler.
+    pub exception: Option<u8>,
+    /// Whether the machine stopped running after this instruction.
+    pub halted: bool,
+}
+
+/// Why a call to one of the `VM::run*` methods returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The machine is no longer running (e.g. after a HALT).
+    Halted,
+    /// The requested number of instructions was executed.
+    StepLimit,
+    /// The `run_until` predicate returned true.
+    Predicate,
+    /// An instruction triggered a watchpoint.
+    Watchpoint,
+}
+
+/// Accesses performed by the instruction currently being executed.
+#[derive(Debug, Default)]
+pub(crate) struct AccessLog {
+    pub(crate) register_writes: Vec<RegisterWrite>,
+    pub(crate) memory_reads: Vec<MemoryRead>,
+    pub(crate) memory_writes: Vec<MemoryWrite>,
+    pub(crate) watch_hits: Vec<WatchHit>,
+}
+
+impl AccessLog {
+    pub(crate) fn clear(&mut self) {
+        self.register_writes.clear();
+        self.memory_reads.clear();
+        self.memory_writes.clear();
+        self.watch_hits.clear();
+    }
+}
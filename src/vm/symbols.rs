@@ -0,0 +1,244 @@
+// Module for symbol tables mapping labels to addresses
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+/* an address further than this from the closest label is shown as a raw address */
+const MAX_LABEL_OFFSET: u16 = 0xFF;
+
+/// Labels and their addresses, as written to and read from `.sym` files.
+///
+/// The file format is the one written by `lc3as` (with tabs after the `//`):
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //  Symbol Name       Page Address
+/// //  ----------------  ------------
+/// //  LOOP              3002
+/// ```
+///
+/// Hand-written files may also list one `NAME ADDRESS` pair per line, with the
+/// address in hex (`3002`, `x3002` or `0x3002`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    addresses: HashMap<String, u16>,
+    /* first label defined at each address, used when naming addresses */
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines `name` at `address`, moving it if it was defined elsewhere.
+    pub fn insert(&mut self, name: &str, address: u16) {
+        let old = self.addresses.insert(name.to_string(), address);
+        if let Some(old) = old.filter(|&old| old != address) {
+            if self.label_at(old) == Some(name) {
+                /* another label left at the old address names it from now on */
+                let next = self
+                    .addresses
+                    .iter()
+                    .filter(|&(_, &other)| other == old)
+                    .map(|(other, _)| other)
+                    .min()
+                    .cloned();
+                match next {
+                    Some(next) => self.labels.insert(old, next),
+                    None => self.labels.remove(&old),
+                };
+            }
+        }
+        self.labels
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    /// Adds every symbol of `other` to this table. The labels `other` names its
+    /// addresses with go first, so they name them here too when the address is new.
+    pub fn extend(&mut self, other: &SymbolTable) {
+        let mut symbols: Vec<(&String, u16)> = other
+            .addresses
+            .iter()
+            .map(|(name, &address)| (name, address))
+            .collect();
+        symbols.sort_by_key(|&(name, address)| {
+            (
+                address,
+                other.label_at(address) != Some(name.as_str()),
+                name,
+            )
+        });
+        for (name, address) in symbols {
+            self.insert(name, address);
+        }
+    }
+
+    /// The address of the label `name`.
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The label defined at exactly `address`.
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// Names `address` relative to the closest label at or before it,
+    /// e.g. `LOOP` or `LOOP+3`, falling back to `x3012`.
+    pub fn format_address(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&label_address, name)) if address == label_address => name.clone(),
+            Some((&label_address, name)) if address - label_address <= MAX_LABEL_OFFSET => {
+                format!("{}+{}", name, address - label_address)
+            }
+            _ => format!("x{:04X}", address),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Symbols in address order, one label per address.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels
+            .iter()
+            .map(|(&address, name)| (address, name.as_str()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table = SymbolTable::new();
+        for (index, line) in text.lines().enumerate() {
+            /* comment lines are lc3as output: skip its headers, read its entries */
+            let (line, lenient) = match line.trim().strip_prefix("//") {
+                Some(rest) => (rest, true),
+                None => (line, false),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match (fields.as_slice(), lenient) {
+                ([], _) => {}
+                ([name, address], _) if parse_address(address).is_some() => {
+                    table.insert(name, parse_address(address).unwrap());
+                }
+                (_, true) => {}
+                (_, false) => {
+                    return Err(format!(
+                        "line {}: expected a label and an address, found '{}'",
+                        index + 1,
+                        line.trim()
+                    ))
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Renders the table in the `lc3as` format.
+    pub fn to_sym_string(&self) -> String {
+        let mut text = String::from(
+            "// Symbol table\n\
+             // Scope level 0:\n\
+             //\tSymbol Name       Page Address\n\
+             //\t----------------  ------------\n",
+        );
+        let mut symbols: Vec<(&str, u16)> = self
+            .addresses
+            .iter()
+            .map(|(name, &address)| (name.as_str(), address))
+            .collect();
+        symbols.sort_by_key(|&(name, address)| (address, name));
+        for (name, address) in symbols {
+            text.push_str(&format!("//\t{:<16}  {:04X}\n", name, address));
+        }
+        text
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_sym_string())
+    }
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix(['x', 'X']))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_address() {
+        let mut table = SymbolTable::new();
+        table.insert("START", 0x3000);
+        table.insert("LOOP", 0x300F);
+
+        assert_eq!(table.format_address(0x3000), "START");
+        assert_eq!(table.format_address(0x3002), "START+2");
+        assert_eq!(table.format_address(0x3012), "LOOP+3");
+        assert_eq!(table.format_address(0x2FFF), "x2FFF");
+        assert_eq!(table.format_address(0x4000), "x4000");
+        assert_eq!(table.address_of("LOOP"), Some(0x300F));
+        assert_eq!(table.label_at(0x300F), Some("LOOP"));
+    }
+
+    #[test]
+    fn test_redefined_label_moves() {
+        let mut table = SymbolTable::new();
+        table.insert("LOOP", 0x3000);
+        table.insert("AGAIN", 0x3000);
+        table.insert("DONE", 0x3004);
+        table.insert("LOOP", 0x3010);
+
+        assert_eq!(table.address_of("LOOP"), Some(0x3010));
+        assert_eq!(table.label_at(0x3010), Some("LOOP"));
+        assert_eq!(table.label_at(0x3000), Some("AGAIN"));
+
+        let mut other = SymbolTable::new();
+        other.insert("DONE", 0x4000);
+        table.extend(&other);
+        assert_eq!(table.label_at(0x3004), None);
+        assert_eq!(table.format_address(0x3005), "AGAIN+5");
+        assert_eq!(table.format_address(0x4000), "DONE");
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn test_sym_round_trip() {
+        let mut table = SymbolTable::new();
+        table.insert("START", 0x3000);
+        table.insert("DATA", 0x3010);
+
+        let text = table.to_sym_string();
+        assert!(text.contains("//\tSTART             3000\n"));
+        assert_eq!(SymbolTable::parse(&text).unwrap(), table);
+    }
+
+    #[test]
+    fn test_parse_hand_written() {
+        let table = SymbolTable::parse("MAIN x3000\n\nHELPER 0x3100\n").unwrap();
+
+        assert_eq!(table.address_of("MAIN"), Some(0x3000));
+        assert_eq!(table.address_of("HELPER"), Some(0x3100));
+        assert_eq!(
+            SymbolTable::parse("MAIN x3000\nOOPS\n").unwrap_err(),
+            "line 2: expected a label and an address, found 'OOPS'"
+        );
+    }
+}
@@ -1,4 +1,5 @@
-pub(crate) enum TrapCode {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapCode {
     Getc = 0x20,  /* get character from keyboard, not echoed onto the terminal */
     Out = 0x21,   /* output a character */
     Puts = 0x22,  /* output a word string */
@@ -28,3 +29,17 @@ impl TryFrom<u16> for TrapCode {
         })
     }
 }
+
+/// How TRAP instructions are carried out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrapMode {
+    /// The standard trap routines are implemented by the VM itself.
+    #[default]
+    Native,
+    /// TRAP jumps to the routine in the trap vector table (x0000-x00FF), loaded with an OS image.
+    ///
+    /// The routine runs with the caller's privilege, as TRAP does not switch to supervisor
+    /// mode. With access control on, a TRAP in user mode raises an access control violation
+    /// on its table entry, so user programs cannot call the OS's routines.
+    Table,
+}
@@ -0,0 +1,76 @@
+// Module for memory watchpoints
+
+/// The kind of memory access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Both reads and writes.
+    Access,
+}
+
+/// A watched range of memory, `start` to `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, kind: WatchKind) -> Self {
+        Watchpoint { start, end, kind }
+    }
+
+    /// A watchpoint on a single address.
+    pub fn at(address: u16, kind: WatchKind) -> Self {
+        Self::new(address, address, kind)
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+
+    /// Whether a read (or write) of `address` triggers this watchpoint.
+    pub fn triggers(&self, address: u16, write: bool) -> bool {
+        self.contains(address)
+            && match self.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true,
+            }
+    }
+}
+
+/// A watched memory access made by an instruction.
+///
+/// `kind` is `Read` or `Write`; for reads `old` and `new` are both the value read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub kind: WatchKind,
+    pub old: u16,
+    pub new: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triggers() {
+        let write = Watchpoint::new(0x4000, 0x400F, WatchKind::Write);
+        let read = Watchpoint::at(0x4000, WatchKind::Read);
+        let access = Watchpoint::at(0x4000, WatchKind::Access);
+
+        assert!(write.triggers(0x4000, true));
+        assert!(write.triggers(0x400F, true));
+        assert!(!write.triggers(0x4010, true));
+        assert!(!write.triggers(0x4000, false));
+        assert!(read.triggers(0x4000, false));
+        assert!(!read.triggers(0x4000, true));
+        assert!(!read.triggers(0x4001, false));
+        assert!(access.triggers(0x4000, false));
+        assert!(access.triggers(0x4000, true));
+    }
+}
@@ -0,0 +1,199 @@
+// Tests running the lc3 binary the way users do
+
+use lc3_vm::asm::assemble;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/* a fresh directory for the files of one test */
+fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("lc3_cli_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/* writes the image assembled from `source` to `dir`, without its .sym and .map */
+fn write_image(dir: &Path, name: &str, source: &str) -> String {
+    let path = dir.join(format!("{}.obj", name));
+    assemble(source)
+        .unwrap()
+        .image
+        .write(&path.to_string_lossy())
+        .unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn lc3(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lc3_vm"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_program_starts_at_the_origin_of_the_first_image() {
+    let dir = test_dir("origin");
+    let program = write_image(
+        &dir,
+        "program",
+        "
+        .ORIG x4000
+        LEA R0, MSG
+        PUTS
+        HALT
+MSG     .STRINGZ \"hi\"
+        .END",
+    );
+    let data = write_image(&dir, "data", ".ORIG x5000\n.FILL #7\n.END");
+
+    let output = lc3(&["--debug", &program, &data], "q\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.starts_with("=> x4000"), "{}", stdout);
+
+    let output = lc3(&["--no-tty", &program, &data], "");
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("hi"));
+}
+
+#[test]
+fn test_start_address() {
+    let dir = test_dir("start");
+    let data = write_image(&dir, "data", ".ORIG x5000\n.FILL #7\n.END");
+    let program = write_image(&dir, "program", ".ORIG x3000\nHALT\n.END");
+
+    /* what the debugger shows before the first instruction */
+    let start = |args: &[&str]| {
+        let output = lc3(&[&["--debug"], args].concat(), "q\n");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout)[..8].to_string()
+    };
+    assert_eq!(start(&[&program, &data]), "=> x3000");
+    /* the first image is where the program starts now, x3000 was always used before */
+    assert_eq!(start(&[&data, &program]), "=> x5000");
+    assert_eq!(start(&[&data, &program, "--start", "x3000"]), "=> x3000");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_output_traps_run_in_the_bundled_os() {
+    let dir = test_dir("os");
+    let program = write_image(
+        &dir,
+        "hello",
+        "
+        .ORIG x3000
+        LEA R0, MSG
+        PUTS
+        LD R0, BANG
+        OUT
+        HALT
+MSG     .STRINGZ \"Hello\"
+BANG    .FILL x21
+        .END",
+    );
+
+    /* the timeout turns a trap routine polling a missing device into a failure */
+    let output = lc3(&["--os", "--no-tty", "--timeout", "10", &program], "");
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hello!Halting the VM...\n"
+    );
+}
+
+#[test]
+fn test_lcov_without_a_source_map() {
+    let dir = test_dir("lcov");
+    let program = write_image(&dir, "halt", ".ORIG x3000\nHALT\n.END");
+    let lcov = dir.join("coverage.info").to_string_lossy().into_owned();
+    let report = dir.join("coverage.txt").to_string_lossy().into_owned();
+
+    let output = lc3(
+        &["--no-tty", "--lcov", &lcov, "--coverage", &report, &program],
+        "",
+    );
+    let lcov = fs::read_to_string(&lcov).unwrap();
+    let report = fs::read_to_string(&report).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    /* the run still succeeds, with the lcov file left empty */
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("no source map for {}, assemble it with lc3 asm\n", program)
+    );
+    assert_eq!(lcov, "");
+    assert!(report.starts_with("Coverage: 1/1 instructions executed"));
+}
+
+#[test]
+fn test_reading_past_the_input_with_the_bundled_os() {
+    let dir = test_dir("os_eof");
+    let program = write_image(
+        &dir,
+        "echo",
+        "
+        .ORIG x3000
+LOOP    GETC
+        OUT
+        BRnzp LOOP
+        .END",
+    );
+
+    /* the OS's GETC stops at the end of the input like the VM's own, instead of polling forever */
+    let native = lc3(&["--input-string", "ab", &program], "");
+    let os = lc3(
+        &["--os", "--input-string", "ab", "--timeout", "10", &program],
+        "",
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    for output in [&native, &os] {
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "ab");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "VM fault: console I/O failed: unexpected end of file\n"
+        );
+    }
+}
+
+#[test]
+fn test_resume_takes_no_images() {
+    let dir = test_dir("resume");
+    let program = write_image(&dir, "halt", ".ORIG x3000\nHALT\n.END");
+    let snapshot = dir.join("halt.lc3s").to_string_lossy().into_owned();
+
+    let output = lc3(&["--no-tty", "--save-on-halt", &snapshot, &program], "");
+    assert!(output.status.success());
+    let output = lc3(&["--no-tty", "--resume", &snapshot], "");
+    assert!(output.status.success());
+
+    /* the snapshot would overwrite them, so they are a usage error rather than ignored */
+    let missing = dir.join("missing.obj").to_string_lossy().into_owned();
+    for args in [
+        &["--resume", &snapshot, &program][..],
+        &["--resume", &snapshot, &missing],
+        &["--resume", &snapshot, "--os"],
+        &["--resume", &snapshot, "--start", "x3000"],
+    ] {
+        let output = lc3(&[&["--no-tty"], args].concat(), "");
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("usage:"));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}